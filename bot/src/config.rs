@@ -0,0 +1,82 @@
+use crate::errors::Result;
+
+use db::DbConfig;
+use log::info;
+use std::env;
+use std::fs;
+
+/// Env var pointing at an optional config file
+const CONFIG_FILE_VAR: &str = "REPOST_CONFIG";
+/// Prefix for db settings, i.e. REPOST_DB_PATH sets DbConfig.path
+const DB_PREFIX: &str = "REPOST_DB_";
+
+/// Builds the db config from defaults, then the config file (if REPOST_CONFIG
+/// is set) and finally the environment, with later sources taking precedence.
+///
+/// The config file uses the same names as the environment, one `KEY=VALUE` per
+/// line with blank lines and lines starting with `#` ignored.
+pub fn load_db_config() -> Result<DbConfig> {
+    let mut config = DbConfig::default();
+
+    if let Ok(file) = env::var(CONFIG_FILE_VAR) {
+        info!("loading config file {file}");
+        apply_db_settings(&mut config, parse_config_file(&fs::read_to_string(file)?))?;
+    }
+    apply_db_settings(&mut config, env::vars())?;
+
+    info!("using db config {config:?}");
+    Ok(config)
+}
+
+fn parse_config_file(contents: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+}
+
+fn apply_db_settings<I>(config: &mut DbConfig, settings: I) -> Result<()>
+where
+    I: Iterator<Item = (String, String)>,
+{
+    for (key, value) in settings {
+        if let Some(db_key) = key.strip_prefix(DB_PREFIX) {
+            config.set(db_key, &value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn test_config_file() -> Result<()> {
+        let contents = "
+            # where the db lives
+            REPOST_DB_PATH = /data/repost.db3
+            REPOST_DB_BUSY_TIMEOUT_MS=1000
+
+            DISCORD_TOKEN=not_a_db_setting
+        ";
+        let mut config = DbConfig::default();
+        apply_db_settings(&mut config, parse_config_file(contents))?;
+
+        assert_eq!(config.path, PathBuf::from("/data/repost.db3"));
+        assert_eq!(config.busy_timeout, Duration::from_millis(1000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_file_bad_key() {
+        let mut config = DbConfig::default();
+        assert!(
+            apply_db_settings(&mut config, parse_config_file("REPOST_DB_COLOUR=blue")).is_err()
+        );
+    }
+}
@@ -10,11 +10,15 @@ pub type Result<T> = result::Result<T, Error>;
 pub enum Error {
     Serenity(serenity::Error),
     Rusqlite(rusqlite::Error),
+    Db(db::Error),
     Url(url::ParseError),
     Reqwest(reqwest::Error),
     ImageError(image::ImageError),
     IoError(std::io::Error),
+    Config(db::ConfigError),
+    Json(serde_json::Error),
     BotMessage,
+    OptedOut,
     ConstStr(&'static str),
 }
 
@@ -23,12 +27,16 @@ impl Display for Error {
         match self {
             Error::Serenity(inner) => fmt::Display::fmt(&inner, f),
             Error::Rusqlite(inner) => fmt::Display::fmt(&inner, f),
+            Error::Db(inner) => fmt::Display::fmt(&inner, f),
             Error::Url(inner) => fmt::Display::fmt(&inner, f),
             Error::Reqwest(inner) => fmt::Display::fmt(&inner, f),
             Error::ImageError(inner) => fmt::Display::fmt(&inner, f),
             Error::IoError(inner) => fmt::Display::fmt(&inner, f),
+            Error::Config(inner) => fmt::Display::fmt(&inner, f),
+            Error::Json(inner) => fmt::Display::fmt(&inner, f),
             Error::ConstStr(inner) => f.write_str(inner),
             Error::BotMessage => f.write_str("Message is from a bot"),
+            Error::OptedOut => f.write_str("Message is from a user that opted out"),
         }
     }
 }
@@ -47,6 +55,12 @@ impl From<rusqlite::Error> for Error {
     }
 }
 
+impl From<db::Error> for Error {
+    fn from(e: db::Error) -> Error {
+        Error::Db(e)
+    }
+}
+
 impl From<url::ParseError> for Error {
     fn from(e: url::ParseError) -> Error {
         Error::Url(e)
@@ -70,3 +84,15 @@ impl From<std::io::Error> for Error {
         Error::IoError(e)
     }
 }
+
+impl From<db::ConfigError> for Error {
+    fn from(e: db::ConfigError) -> Error {
+        Error::Config(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
@@ -0,0 +1,383 @@
+use crate::errors::{Error, Result};
+use crate::handler::parse_filter;
+
+use chrono::{DateTime, Utc};
+use db::structs::{
+    ExportImage, ExportLink, ExportMessage, ExportRepost, ExportUser, MessageFilter,
+};
+use db::{read_only_db_async, ReadOnlyDb};
+use log::info;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExportKind {
+    Links,
+    Images,
+    Messages,
+    Reposts,
+    Users,
+}
+
+impl ExportKind {
+    pub const ALL: [ExportKind; 5] = [
+        ExportKind::Links,
+        ExportKind::Images,
+        ExportKind::Messages,
+        ExportKind::Reposts,
+        ExportKind::Users,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            ExportKind::Links => "links",
+            ExportKind::Images => "images",
+            ExportKind::Messages => "messages",
+            ExportKind::Reposts => "reposts",
+            ExportKind::Users => "users",
+        }
+    }
+
+    pub const fn description(&self) -> &'static str {
+        match self {
+            ExportKind::Links => "Every link posted and the message it was in",
+            ExportKind::Images => "Every image posted and its hash",
+            ExportKind::Messages => "Every message stored, without the content",
+            ExportKind::Reposts => "Every repost found and what it was a repost of",
+            ExportKind::Users => "Message and repost counts for each user",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ExportKind> {
+        ExportKind::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub const fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        [ExportFormat::Csv, ExportFormat::Json]
+            .into_iter()
+            .find(|format| format.extension().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ExportRequest {
+    pub kind: ExportKind,
+    pub format: ExportFormat,
+    pub filter: MessageFilter,
+}
+
+impl ExportRequest {
+    /// Parses `<kind> [csv|json]` followed by any filters, CSV is the default
+    pub fn parse(args: &[&str]) -> Option<ExportRequest> {
+        let (kind, args) = args.split_first()?;
+        let (formats, filters): (Vec<&str>, Vec<&str>) = args
+            .iter()
+            .partition(|arg| ExportFormat::from_name(arg).is_some());
+        Some(ExportRequest {
+            kind: ExportKind::from_name(kind)?,
+            format: formats
+                .last()
+                .and_then(|format| ExportFormat::from_name(format))
+                .unwrap_or(ExportFormat::Csv),
+            filter: parse_filter(&filters, Utc::now())?,
+        })
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.kind.name(), self.format.extension())
+    }
+}
+
+/// A row in an export, ids are written as strings like discord does so they don't lose
+/// precision in anything that reads numbers as floats
+trait Record {
+    const COLUMNS: &'static [&'static str];
+
+    fn values(&self) -> Vec<Value>;
+}
+
+fn id(id: u64) -> Value {
+    Value::String(id.to_string())
+}
+
+fn optional_id(maybe_id: Option<u64>) -> Value {
+    maybe_id.map_or(Value::Null, id)
+}
+
+fn time(time: DateTime<Utc>) -> Value {
+    Value::String(time.to_rfc3339())
+}
+
+impl Record for ExportMessage {
+    const COLUMNS: &'static [&'static str] = &[
+        "id",
+        "channel",
+        "channel_name",
+        "author",
+        "username",
+        "created_at",
+    ];
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            id(self.id),
+            id(self.channel),
+            self.channel_name.clone().into(),
+            optional_id(self.author),
+            self.username.clone().into(),
+            time(self.created_at),
+        ]
+    }
+}
+
+impl Record for ExportLink {
+    const COLUMNS: &'static [&'static str] =
+        &["message", "channel", "author", "created_at", "link"];
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            id(self.message),
+            id(self.channel),
+            optional_id(self.author),
+            time(self.created_at),
+            self.link.clone().into(),
+        ]
+    }
+}
+
+impl Record for ExportImage {
+    const COLUMNS: &'static [&'static str] =
+        &["message", "channel", "author", "created_at", "url", "hash"];
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            id(self.message),
+            id(self.channel),
+            optional_id(self.author),
+            time(self.created_at),
+            self.url.clone().into(),
+            self.hash.clone().into(),
+        ]
+    }
+}
+
+impl Record for ExportRepost {
+    const COLUMNS: &'static [&'static str] = &[
+        "message",
+        "original",
+        "kind",
+        "link",
+        "distance",
+        "author",
+        "original_author",
+        "created_at",
+    ];
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            id(self.message),
+            id(self.original),
+            self.kind.clone().into(),
+            self.link.clone().into(),
+            self.distance.into(),
+            optional_id(self.author),
+            optional_id(self.original_author),
+            time(self.created_at),
+        ]
+    }
+}
+
+impl Record for ExportUser {
+    const COLUMNS: &'static [&'static str] = &["id", "username", "messages", "reposts", "reposted"];
+
+    fn values(&self) -> Vec<Value> {
+        vec![
+            id(self.id),
+            self.username.clone().into(),
+            self.messages.into(),
+            self.reposts.into(),
+            self.reposted.into(),
+        ]
+    }
+}
+
+fn csv_field(value: &Value) -> String {
+    let field = match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+fn to_csv<R: Record>(records: &[R]) -> String {
+    let mut csv = R::COLUMNS.join(",");
+    csv.push('\n');
+    for record in records {
+        let row = record.values().iter().map(csv_field).collect::<Vec<_>>();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn to_json<R: Record>(records: &[R]) -> Result<String> {
+    let rows = records
+        .iter()
+        .map(|record| {
+            let row = R::COLUMNS
+                .iter()
+                .map(|column| column.to_string())
+                .zip(record.values())
+                .collect::<Map<String, Value>>();
+            Value::Object(row)
+        })
+        .collect::<Vec<_>>();
+    Ok(serde_json::to_string_pretty(&rows)?)
+}
+
+fn write<R: Record>(records: &[R], format: ExportFormat) -> Result<(usize, Vec<u8>)> {
+    let data = match format {
+        ExportFormat::Csv => to_csv(records),
+        ExportFormat::Json => to_json(records)?,
+    };
+    Ok((records.len(), data.into_bytes()))
+}
+
+/// Runs the export for the server, returning how many rows it has and the file contents
+pub async fn export(server_id: u64, request: ExportRequest) -> Result<(usize, Vec<u8>)> {
+    let (filter, format) = (request.filter, request.format);
+    match request.kind {
+        ExportKind::Links => write(
+            &read_only_db_async(move |db| db.export_links(server_id, filter)).await?,
+            format,
+        ),
+        ExportKind::Images => write(
+            &read_only_db_async(move |db| db.export_images(server_id, filter)).await?,
+            format,
+        ),
+        ExportKind::Messages => write(
+            &read_only_db_async(move |db| db.export_messages(server_id, filter)).await?,
+            format,
+        ),
+        ExportKind::Reposts => write(
+            &read_only_db_async(move |db| db.export_reposts(server_id, filter)).await?,
+            format,
+        ),
+        ExportKind::Users => write(
+            &read_only_db_async(move |db| db.export_users(server_id, filter)).await?,
+            format,
+        ),
+    }
+}
+
+const CLI_USAGE: &str = "Usage: bot export <server id> <file.csv|file.json> <links|images|messages|reposts|users> [channel id] [from:YYYY-MM-DD] [to:YYYY-MM-DD]";
+
+/// Exports from the command line, the format comes from the output file's extension
+pub async fn run(args: &[String]) -> Result<()> {
+    let (server_id, path) = match args {
+        [server_id, path, ..] => (
+            server_id
+                .parse::<u64>()
+                .map_err(|_| Error::ConstStr(CLI_USAGE))?,
+            Path::new(path),
+        ),
+        _ => return Err(Error::ConstStr(CLI_USAGE)),
+    };
+    let format = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(ExportFormat::from_name)
+        .ok_or(Error::ConstStr("Output file must end in .csv or .json"))?;
+    let args = args[2..].iter().map(String::as_str).collect::<Vec<&str>>();
+    let mut request = ExportRequest::parse(&args).ok_or(Error::ConstStr(CLI_USAGE))?;
+    request.format = format;
+
+    let (rows, data) = export(server_id, request).await?;
+    fs::write(path, data)?;
+    info!(
+        "exported {rows} {} to {}",
+        request.kind.name(),
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_request() {
+        let request = ExportRequest::parse(&["reposts", "to:2023-01-31", "json", "<#5>"]).unwrap();
+        assert_eq!(request.kind, ExportKind::Reposts);
+        assert_eq!(request.format, ExportFormat::Json);
+        assert_eq!(request.filter.channel, Some(5));
+        assert!(request.filter.since.is_none() && request.filter.until.is_some());
+        assert_eq!(request.file_name(), "reposts.json");
+
+        let request = ExportRequest::parse(&["Links", "from:2023-01-01"]).unwrap();
+        assert_eq!(request.format, ExportFormat::Csv);
+        assert!(request.filter.since.is_some());
+
+        assert!(ExportRequest::parse(&[]).is_none());
+        assert!(ExportRequest::parse(&["pins"]).is_none());
+        assert!(ExportRequest::parse(&["links", "#general"]).is_none());
+    }
+
+    #[test]
+    fn test_write() -> Result<()> {
+        let links = vec![ExportLink {
+            message: 1,
+            channel: 2,
+            author: None,
+            created_at: Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap(),
+            link: String::from("https://example.com/?a=1,b=\"2\""),
+        }];
+
+        let (rows, csv) = write(&links, ExportFormat::Csv)?;
+        assert_eq!(rows, 1);
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "message,channel,author,created_at,link\n\
+            1,2,,2023-01-01T00:00:00+00:00,\"https://example.com/?a=1,b=\"\"2\"\"\"\n"
+        );
+
+        let (_, json) = write(&links, ExportFormat::Json)?;
+        let json: Value = serde_json::from_slice(&json)?;
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "message": "1",
+                "channel": "2",
+                "author": null,
+                "created_at": "2023-01-01T00:00:00+00:00",
+                "link": "https://example.com/?a=1,b=\"2\"",
+            }])
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use super::{process_message, regular_text_msg};
+use crate::errors::{Error, Result};
+
+use chrono::{DateTime, Utc};
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
+use lazy_static::lazy_static;
+use log::{error, info, trace, warn};
+use serenity::{
+    model::{channel::Message, id::GuildId},
+    prelude::*,
+};
+use std::time::Duration;
+
+// most messages discord will return in one request
+const PAGE_SIZE: u64 = 100;
+
+// serenity already waits out discord's rate limits, this just stops backfills from using
+// up the whole limit and slowing everything else down
+const PAGE_INTERVAL: Duration = Duration::from_secs(3);
+
+// how often to check for newly started backfills when there's nothing to do
+const IDLE_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    // messages sent after the bot connected came in live, so catching up starts before it
+    static ref CONNECTED_AT: DateTime<Utc> = Utc::now();
+}
+
+/// Records when the bot first connected, before any messages come in
+pub fn mark_connected() {
+    lazy_static::initialize(&CONNECTED_AT);
+}
+
+/// Processes a page of messages from the channel like any message that came in whilst the
+/// bot was online, just without replying to reposts
+async fn process_page(ctx: &Context, server_id: u64, messages: Vec<Message>) {
+    for mut msg in messages {
+        if msg.author.bot || !regular_text_msg(msg.kind) {
+            continue;
+        }
+        if msg.guild_id.is_none() {
+            msg.guild_id = Some(GuildId(server_id));
+        }
+        match process_message(ctx, &msg, false).await {
+            Ok(_) | Err(Error::OptedOut) => (),
+            Err(why) => warn!("Failed to load old message {} with error {why:?}", msg.id),
+        }
+    }
+}
+
+/// Loads the next page of history for whichever channel in the server is next in line.
+/// Returns how many messages were loaded, or None if there's nothing to backfill.
+async fn backfill_page(ctx: &Context, server_id: u64) -> Result<Option<usize>> {
+    let backfill = match read_only_db_async(move |db| db.get_next_backfill(server_id)).await? {
+        Some(backfill) => backfill,
+        None => return Ok(None),
+    };
+    let channel_id = backfill.channel;
+    let query = backfill.cursor.map_or_else(
+        || format!("?limit={PAGE_SIZE}"),
+        |cursor| format!("?limit={PAGE_SIZE}&before={cursor}"),
+    );
+
+    let messages = match ctx.http.get_messages(channel_id, &query).await {
+        Ok(messages) => messages,
+        Err(why) => {
+            // otherwise the same channel would be picked again straight away
+            warn!("pausing backfill of channel {channel_id} after failing to load messages");
+            writable_db_async(move |db| db.pause_backfill(channel_id)).await?;
+            return Err(why.into());
+        }
+    };
+
+    let len = messages.len();
+    let oldest = messages.iter().map(|msg| *msg.id.as_u64()).min();
+    process_page(ctx, server_id, messages).await;
+
+    // a short page means there's nothing older left in the channel
+    let cursor = if (len as u64) < PAGE_SIZE {
+        None
+    } else {
+        oldest
+    };
+    writable_db_async(move |db| db.advance_backfill(channel_id, cursor, len as u64)).await?;
+    info!(
+        "backfill of #{} loaded {len} messages, {} so far{}",
+        backfill.channel_name.unwrap_or_default(),
+        backfill.messages + len as u64,
+        if cursor.is_none() { ", finished" } else { "" }
+    );
+    Ok(Some(len))
+}
+
+/// Loads everything sent in the channel since the newest message stored before the bot
+/// connected, which is whatever was missed whilst it was offline. Channels with nothing
+/// stored are left to backfills.
+async fn catch_up_channel(ctx: &Context, server_id: u64, channel_id: u64) -> Result<usize> {
+    let connected_at = *CONNECTED_AT;
+    let newest =
+        read_only_db_async(move |db| db.get_newest_message_in_channel(channel_id, connected_at))
+            .await?;
+    let mut after = match newest {
+        Some(newest) => newest,
+        None => return Ok(0),
+    };
+    let mut loaded = 0;
+    loop {
+        let query = format!("?limit={PAGE_SIZE}&after={after}");
+        let messages = ctx.http.get_messages(channel_id, &query).await?;
+        let len = messages.len();
+        after = messages
+            .iter()
+            .map(|msg| *msg.id.as_u64())
+            .max()
+            .unwrap_or(after);
+        process_page(ctx, server_id, messages).await;
+        loaded += len;
+        if (len as u64) < PAGE_SIZE {
+            return Ok(loaded);
+        }
+        tokio::time::sleep(PAGE_INTERVAL).await;
+    }
+}
+
+/// Catches up on every visible channel in the server, run when the bot (re)connects
+pub async fn catch_up(ctx: &Context, server_id: u64) {
+    let channels = match read_only_db_async(move |db| db.get_known_channels(server_id)).await {
+        Ok(channels) => channels,
+        Err(why) => {
+            error!("failed to load channels to catch up on for server {server_id} {why:?}");
+            return;
+        }
+    };
+    for channel in channels {
+        match catch_up_channel(ctx, server_id, channel.id).await {
+            Ok(0) => (),
+            Ok(loaded) => info!("caught up on {loaded} messages in channel {}", channel.id),
+            Err(why) => warn!("failed to catch up on channel {} {why:?}", channel.id),
+        }
+    }
+}
+
+/// Works through the server's backfills one page at a time for as long as the bot is in it
+pub async fn run(ctx: &Context, server_id: u64) {
+    loop {
+        if ctx.cache.guild(server_id).is_none() {
+            info!("no longer in server {server_id}, stopping backfill");
+            break;
+        }
+        let wait = match backfill_page(ctx, server_id).await {
+            Ok(Some(_)) => PAGE_INTERVAL,
+            Ok(None) => IDLE_INTERVAL,
+            Err(why) => {
+                error!("backfill failed for server {server_id} with err {why:?}");
+                IDLE_INTERVAL
+            }
+        };
+        trace!("backfill task sleeping {wait:?}");
+        tokio::time::sleep(wait).await;
+    }
+}
@@ -0,0 +1,146 @@
+use super::channel::parse_channel;
+use super::{is_server_manager, Invocation};
+use crate::errors::Result;
+use crate::structs::reply::Reply;
+
+use db::structs::Backfill;
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
+use itertools::Itertools;
+use serenity::{model::id::ChannelId, prelude::*};
+
+const USAGE: &str = "Usage: !rpm backfill [status|start|pause|prioritise] [#channel]";
+
+fn describe(backfill: &Backfill) -> String {
+    let state = if backfill.done {
+        "finished"
+    } else if backfill.paused {
+        "paused"
+    } else {
+        "in progress"
+    };
+    format!(
+        "#{}: {} messages loaded, {state}",
+        backfill.channel_name.as_deref().unwrap_or_default(),
+        backfill.messages
+    )
+}
+
+async fn status(server_id: u64) -> Result<String> {
+    let backfills = read_only_db_async(move |db| db.get_backfills(server_id)).await?;
+    if backfills.is_empty() {
+        return Ok(String::from(
+            "No channels are being backfilled, start with !rpm backfill start [#channel]",
+        ));
+    }
+    Ok(backfills.iter().map(describe).join("\n"))
+}
+
+/// Makes sure the channel is one we know about in this server and can read
+async fn known_channel(server_id: u64, channel_id: ChannelId) -> Result<bool> {
+    let channels = read_only_db_async(move |db| db.get_known_channels(server_id)).await?;
+    Ok(channels.iter().any(|c| c.id == *channel_id.as_u64()))
+}
+
+async fn run_backfill(
+    server_id: u64,
+    action: &str,
+    channel_id: Option<ChannelId>,
+    current: ChannelId,
+) -> Result<String> {
+    let response = match (action, channel_id) {
+        ("status", None) => status(server_id).await?,
+        ("start", None) => {
+            writable_db_async(move |db| db.start_server_backfill(server_id)).await?;
+            String::from("Started loading the history of every channel")
+        }
+        ("pause", None) => {
+            writable_db_async(move |db| db.pause_server_backfill(server_id)).await?;
+            String::from("Paused loading history for every channel")
+        }
+        ("start" | "pause" | "prioritise" | "prioritize", channel_id) => {
+            let channel_id = channel_id.unwrap_or(current);
+            if !known_channel(server_id, channel_id).await? {
+                return Ok(String::from(
+                    "Channel isn't in this server or the bot can't read it",
+                ));
+            }
+            let id = *channel_id.as_u64();
+            match action {
+                "start" => {
+                    writable_db_async(move |db| db.start_backfill(id)).await?;
+                    format!("Started loading the history of <#{id}>")
+                }
+                "pause" => {
+                    writable_db_async(move |db| db.pause_backfill(id)).await?;
+                    format!("Paused loading the history of <#{id}>")
+                }
+                _ => {
+                    writable_db_async(move |db| {
+                        db.start_backfill(id)?;
+                        db.prioritise_backfill(id)
+                    })
+                    .await?;
+                    format!("<#{id}> will be backfilled next")
+                }
+            }
+        }
+        _ => USAGE.to_string(),
+    };
+    Ok(response)
+}
+
+pub async fn backfill<'a>(
+    ctx: &Context,
+    invocation: Invocation<'a>,
+    args: &[&str],
+) -> Result<Reply<'a>> {
+    if !is_server_manager(ctx, invocation).await? {
+        return Ok(Reply::new_const(
+            "You need the Manage Server permission to backfill channels",
+            invocation.reply(),
+        ));
+    }
+
+    let server_id = *invocation.guild_id()?.as_u64();
+    let response = match args {
+        [] => status(server_id).await?,
+        [action] => run_backfill(server_id, action, None, invocation.channel_id()).await?,
+        [action, channel] => match parse_channel(channel) {
+            Some(channel_id) => {
+                run_backfill(server_id, action, Some(channel_id), invocation.channel_id()).await?
+            }
+            None => USAGE.to_string(),
+        },
+        _ => USAGE.to_string(),
+    };
+
+    Ok(Reply::new(response, invocation.reply()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        let mut backfill = Backfill {
+            channel: 1,
+            channel_name: Some(String::from("general")),
+            cursor: Some(100),
+            messages: 250,
+            paused: false,
+            done: false,
+        };
+        assert_eq!(
+            describe(&backfill),
+            "#general: 250 messages loaded, in progress"
+        );
+        backfill.paused = true;
+        assert_eq!(describe(&backfill), "#general: 250 messages loaded, paused");
+        backfill.done = true;
+        assert_eq!(
+            describe(&backfill),
+            "#general: 250 messages loaded, finished"
+        );
+    }
+}
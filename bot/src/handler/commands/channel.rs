@@ -0,0 +1,125 @@
+use super::{is_server_manager, Invocation};
+use crate::errors::Result;
+use crate::handler::{bot_read_channel_permission, threads};
+use crate::structs::reply::Reply;
+
+use db::structs::ChannelMode;
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
+use itertools::Itertools;
+use serenity::{
+    model::{
+        channel::ChannelType,
+        id::{ChannelId, GuildId},
+    },
+    prelude::*,
+};
+
+const USAGE: &str = "Usage: !rpm channel [off|silent|reply|react] [#channel]";
+
+/// Parses either a channel mention from a message or a bare id from a slash command
+pub(super) fn parse_channel(arg: &str) -> Option<ChannelId> {
+    arg.trim_start_matches("<#")
+        .trim_end_matches('>')
+        .parse()
+        .ok()
+        .map(ChannelId)
+}
+
+async fn list_modes(server_id: u64) -> Result<String> {
+    let modes = read_only_db_async(move |db| db.get_channel_modes(server_id)).await?;
+    if modes.is_empty() {
+        return Ok(format!(
+            "All channels are using the default mode {}",
+            ChannelMode::default()
+        ));
+    }
+    Ok(format!(
+        "Channels not using the default mode {}:\n{}",
+        ChannelMode::default(),
+        modes
+            .into_iter()
+            .map(|(name, mode)| format!("#{name}: {mode}"))
+            .join("\n")
+    ))
+}
+
+async fn set_mode(
+    ctx: &Context,
+    server_id: u64,
+    channel_id: ChannelId,
+    mode: ChannelMode,
+) -> Result<String> {
+    // threads aren't cached with the other channels
+    let channel = match ctx.cache.guild_channel(channel_id) {
+        Some(channel) => Some(channel),
+        None => threads::get_thread(ctx, GuildId(server_id), channel_id)
+            .await
+            .ok(),
+    };
+    let channel = match channel {
+        Some(channel) if *channel.guild_id.as_u64() == server_id => channel,
+        _ => return Ok(String::from("Channel isn't in this server")),
+    };
+
+    // make sure the channel is in the db even if nothing has been posted in it yet
+    let id = *channel.id.as_u64();
+    if matches!(
+        channel.kind,
+        ChannelType::NewsThread | ChannelType::PublicThread | ChannelType::PrivateThread
+    ) {
+        threads::store_thread(ctx, &channel).await?;
+        writable_db_async(move |db| db.set_channel_mode(id, mode)).await?;
+    } else {
+        let visible = bot_read_channel_permission(ctx, &channel).await;
+        let name = channel.name.clone();
+        writable_db_async(move |db| {
+            db.update_channel(id, server_id, &name, visible)?;
+            db.set_channel_mode(id, mode)
+        })
+        .await?;
+    }
+
+    Ok(format!("#{} set to {mode}", channel.name))
+}
+
+pub async fn channel<'a>(
+    ctx: &Context,
+    invocation: Invocation<'a>,
+    args: &[&str],
+) -> Result<Reply<'a>> {
+    if !is_server_manager(ctx, invocation).await? {
+        return Ok(Reply::new_const(
+            "You need the Manage Server permission to change channel modes",
+            invocation.reply(),
+        ));
+    }
+
+    let server_id = *invocation.guild_id()?.as_u64();
+    let response = match args {
+        [] => list_modes(server_id).await?,
+        [mode] | [mode, _] => {
+            let channel_id = args
+                .get(1)
+                .map_or_else(|| Some(invocation.channel_id()), |arg| parse_channel(arg));
+            match (mode.parse::<ChannelMode>(), channel_id) {
+                (Ok(mode), Some(channel_id)) => set_mode(ctx, server_id, channel_id, mode).await?,
+                _ => USAGE.to_string(),
+            }
+        }
+        _ => USAGE.to_string(),
+    };
+
+    Ok(Reply::new(response, invocation.reply()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_channel() {
+        assert_eq!(parse_channel("<#1234>"), Some(ChannelId(1234)));
+        assert_eq!(parse_channel("1234"), Some(ChannelId(1234)));
+        assert_eq!(parse_channel("#memes"), None);
+    }
+}
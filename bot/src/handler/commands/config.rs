@@ -0,0 +1,96 @@
+use super::{is_server_manager, Invocation};
+use crate::errors::{Error, Result};
+use crate::handler::settings::{ServerSettings, SettingKey};
+use crate::structs::reply::Reply;
+
+use db::{writable_db_async, WriteableDb};
+use itertools::Itertools;
+use serenity::prelude::*;
+
+const USAGE: &str = "Usage: !rpm config get|set|reset <key> [value]";
+
+fn parse_key(name: Option<&&str>) -> std::result::Result<SettingKey, String> {
+    let name = name.ok_or_else(|| USAGE.to_string())?;
+    SettingKey::from_name(name).ok_or_else(|| {
+        format!(
+            "Unknown setting {name}, expected one of: {}",
+            SettingKey::ALL.iter().map(|k| k.name()).join(", ")
+        )
+    })
+}
+
+fn list_settings(settings: &ServerSettings) -> String {
+    SettingKey::ALL
+        .iter()
+        .map(|key| {
+            format!(
+                "{} = {} ({})",
+                key.name(),
+                settings.get(*key),
+                key.description()
+            )
+        })
+        .join("\n")
+}
+
+async fn run_config(server_id: u64, args: &[&str]) -> Result<String> {
+    let mut settings = ServerSettings::load(server_id).await?;
+
+    let response = match args.first().copied() {
+        None => list_settings(&settings),
+        Some("get") if args.len() == 1 => list_settings(&settings),
+        Some("get") => match parse_key(args.get(1)) {
+            Ok(key) => format!("{} = {}", key.name(), settings.get(key)),
+            Err(why) => why,
+        },
+        Some("set") if args.len() < 3 => USAGE.to_string(),
+        Some("set") => match parse_key(args.get(1)) {
+            Ok(key) => match settings.set(key, &args[2..].join(" ")) {
+                Ok(_) => {
+                    let value = settings.get(key);
+                    let stored = value.clone();
+                    writable_db_async(move |db| {
+                        db.set_server_setting(server_id, key.name(), &stored)
+                    })
+                    .await?;
+                    format!("{} set to {value}", key.name())
+                }
+                Err(Error::ConstStr(why)) => format!("Invalid value for {}: {why}", key.name()),
+                Err(why) => return Err(why),
+            },
+            Err(why) => why,
+        },
+        Some("reset") => match parse_key(args.get(1)) {
+            Ok(key) => {
+                writable_db_async(move |db| db.reset_server_setting(server_id, key.name())).await?;
+                format!(
+                    "{} reset to {}",
+                    key.name(),
+                    ServerSettings::default().get(key)
+                )
+            }
+            Err(why) => why,
+        },
+        Some(_) => USAGE.to_string(),
+    };
+    Ok(response)
+}
+
+pub async fn config<'a>(
+    ctx: &Context,
+    invocation: Invocation<'a>,
+    args: &[&str],
+) -> Result<Reply<'a>> {
+    if !is_server_manager(ctx, invocation).await? {
+        return Ok(Reply::new_const(
+            "You need the Manage Server permission to change settings",
+            invocation.reply(),
+        ));
+    }
+
+    let server_id = *invocation.guild_id()?.as_u64();
+    Ok(Reply::new(
+        run_config(server_id, args).await?,
+        invocation.reply(),
+    ))
+}
@@ -0,0 +1,72 @@
+use super::Invocation;
+use crate::errors::Result;
+use crate::structs::reply::Reply;
+
+use db::{writable_db_async, WriteableDb};
+use log::info;
+use serenity::prelude::*;
+
+const USAGE: &str = "Usage: !rpm erase user|server <id>";
+
+/// Parses either a mention or a bare id
+fn parse_id(arg: &str) -> Option<u64> {
+    arg.trim_start_matches("<@")
+        .trim_start_matches('!')
+        .trim_end_matches('>')
+        .parse()
+        .ok()
+}
+
+async fn is_owner(ctx: &Context, invocation: Invocation<'_>) -> Result<bool> {
+    let info = ctx.http.get_current_application_info().await?;
+    Ok(info.owner.id == invocation.author().id)
+}
+
+pub async fn erase<'a>(
+    ctx: &Context,
+    invocation: Invocation<'a>,
+    args: &[&str],
+) -> Result<Reply<'a>> {
+    if !is_owner(ctx, invocation).await? {
+        return Ok(Reply::new_const(
+            "Only the owner of the bot can erase data",
+            invocation.reply(),
+        ));
+    }
+
+    let response = match (args.first().copied(), args.get(1).and_then(|a| parse_id(a))) {
+        (Some("user"), Some(user_id)) => {
+            info!(
+                "{} requested erasing user {user_id}",
+                invocation.author().name
+            );
+            writable_db_async(move |mut db| db.erase_user(user_id)).await?;
+            format!("Erased all data for user {user_id}")
+        }
+        (Some("server"), Some(server_id)) => {
+            info!(
+                "{} requested erasing server {server_id}",
+                invocation.author().name
+            );
+            writable_db_async(move |mut db| db.erase_server(server_id)).await?;
+            format!("Erased all data for server {server_id}")
+        }
+        _ => USAGE.to_string(),
+    };
+
+    // the command message may have just been erased, so don't reply to it directly
+    Ok(Reply::new(response, invocation.channel_reply()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(parse_id("1234"), Some(1234));
+        assert_eq!(parse_id("<@1234>"), Some(1234));
+        assert_eq!(parse_id("<@!1234>"), Some(1234));
+        assert_eq!(parse_id("someone"), None);
+    }
+}
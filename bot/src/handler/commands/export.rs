@@ -0,0 +1,43 @@
+use super::{is_server_manager, Invocation};
+use crate::errors::Result;
+use crate::export::{export as run_export, ExportRequest};
+use crate::structs::reply::Reply;
+
+use serenity::prelude::*;
+
+const USAGE: &str = "Usage: !rpm export <links|images|messages|reposts|users> [csv|json] [today|week|month|year] [from:YYYY-MM-DD] [to:YYYY-MM-DD] [#channel]";
+
+// discord's upload limit for servers without boosts
+const MAX_FILE_SIZE: usize = 8 * 1024 * 1024;
+
+pub async fn export<'a>(
+    ctx: &Context,
+    invocation: Invocation<'a>,
+    args: &[&str],
+) -> Result<Reply<'a>> {
+    if !is_server_manager(ctx, invocation).await? {
+        return Ok(Reply::new_const(
+            "You need the Manage Server permission to export data",
+            invocation.reply(),
+        ));
+    }
+    let request = match ExportRequest::parse(args) {
+        Some(request) => request,
+        None => return Ok(Reply::new_const(USAGE, invocation.reply())),
+    };
+
+    let server_id = *invocation.guild_id()?.as_u64();
+    let (rows, data) = run_export(server_id, request).await?;
+    if data.len() > MAX_FILE_SIZE {
+        return Ok(Reply::new_const(
+            "Export is too big to upload, try a shorter date range or a single channel",
+            invocation.reply(),
+        ));
+    }
+    Ok(Reply::new_file(
+        format!("Exported {rows} {}", request.kind.name()),
+        request.file_name(),
+        data,
+        invocation.reply(),
+    ))
+}
@@ -0,0 +1,76 @@
+use super::channel::parse_channel;
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use db::structs::MessageFilter;
+
+/// Midnight UTC at the start of a YYYY-MM-DD date
+fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
+}
+
+/// Parses args limiting a command to recent messages with `today`, `week`, `month` or
+/// `year`, a date range with `from:YYYY-MM-DD` and/or `to:YYYY-MM-DD` (both inclusive) and a
+/// channel. Returns None if any arg isn't one of those.
+pub(crate) fn parse_filter(args: &[&str], now: DateTime<Utc>) -> Option<MessageFilter> {
+    let mut filter = MessageFilter::default();
+    for arg in args {
+        // days are in UTC, there's nothing to say which timezone the server is in
+        let since = match arg.to_ascii_lowercase().as_str() {
+            "today" => Some(Utc.from_utc_datetime(&now.date_naive().and_hms_opt(0, 0, 0)?)),
+            "week" => Some(now - Duration::days(7)),
+            "month" => Some(now - Duration::days(30)),
+            "year" => Some(now - Duration::days(365)),
+            _ => None,
+        };
+        if since.is_some() {
+            filter.since = since;
+        } else if let Some(date) = arg.strip_prefix("from:") {
+            filter.since = Some(parse_date(date)?);
+        } else if let Some(date) = arg.strip_prefix("to:") {
+            filter.until = Some(parse_date(date)? + Duration::days(1));
+        } else {
+            filter.channel = Some(*parse_channel(arg)?.as_u64());
+        }
+    }
+    Some(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filter() {
+        let now = Utc.with_ymd_and_hms(2023, 3, 15, 18, 30, 0).unwrap();
+        assert_eq!(parse_filter(&[], now), Some(MessageFilter::default()));
+
+        let filter = parse_filter(&["today", "<#5>"], now).unwrap();
+        assert_eq!(
+            filter.since,
+            Utc.with_ymd_and_hms(2023, 3, 15, 0, 0, 0).single()
+        );
+        assert_eq!(filter.until, None);
+        assert_eq!(filter.channel, Some(5));
+
+        let filter = parse_filter(&["Week"], now).unwrap();
+        assert_eq!(
+            filter.since,
+            Utc.with_ymd_and_hms(2023, 3, 8, 18, 30, 0).single()
+        );
+
+        // to is inclusive
+        let filter = parse_filter(&["from:2023-01-01", "to:2023-01-31"], now).unwrap();
+        assert_eq!(
+            filter.since,
+            Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).single()
+        );
+        assert_eq!(
+            filter.until,
+            Utc.with_ymd_and_hms(2023, 2, 1, 0, 0, 0).single()
+        );
+
+        assert_eq!(parse_filter(&["from:yesterday"], now), None);
+        assert_eq!(parse_filter(&["#general"], now), None);
+    }
+}
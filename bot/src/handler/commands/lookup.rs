@@ -0,0 +1,115 @@
+use super::Invocation;
+use crate::errors::Result;
+use crate::handler::images::{find_url_matches, ImageProcesser};
+use crate::handler::settings::ServerSettings;
+use crate::structs::reply::Reply;
+
+use db::structs::Message;
+use itertools::Itertools;
+use std::collections::HashMap;
+
+const USAGE: &str =
+    "Usage: !rpm lookup [image url], either attach the image or link to it, wrap links in <> to stop them embedding";
+
+// keeps the reply well under discord's message length limit
+const MAX_MATCHES: usize = 10;
+
+fn describe(matches: &[(Message, u32)]) -> String {
+    if matches.is_empty() {
+        return String::from("Nothing like that has been posted here");
+    }
+    // a message with several matching images is only listed for its closest one
+    let closest = matches
+        .iter()
+        .sorted_by_key(|(msg, distance)| (*distance, msg.created_at))
+        .unique_by(|(msg, _)| msg.id)
+        .collect_vec();
+    let mut lines = closest
+        .iter()
+        .take(MAX_MATCHES)
+        .map(|(msg, distance)| {
+            format!(
+                "Distance {distance}: posted on {} {}",
+                msg.created_at.format("%Y-%m-%d"),
+                msg.uri()
+            )
+        })
+        .collect_vec();
+    if closest.len() > MAX_MATCHES {
+        lines.push(format!("...and {} more", closest.len() - MAX_MATCHES));
+    }
+    lines.join("\n")
+}
+
+/// Looks for images like the ones attached or linked without storing them, so checking
+/// doesn't count as a repost
+pub async fn lookup<'a>(invocation: Invocation<'a>, args: &[&str]) -> Result<Reply<'a>> {
+    let urls = args
+        .iter()
+        .map(|url| {
+            url.trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+        .collect_vec();
+    let attached = match invocation {
+        Invocation::Message(msg) => Some(ImageProcesser::from_message(msg)?),
+        Invocation::Interaction(_) => None,
+    };
+
+    let server_id = *invocation.guild_id()?.as_u64();
+    let current_id = invocation.message_id().map_or(0, |id| *id.as_u64());
+    let settings = ServerSettings::load(server_id).await?;
+    let mut hashed = HashMap::new();
+    let mut matches =
+        find_url_matches(&urls, current_id, server_id, &settings, &mut hashed).await?;
+    if let Some(attached) = attached {
+        matches.extend(attached.find_matches(&settings, &mut hashed).await?);
+    }
+
+    if hashed.values().all(Option::is_none) {
+        return Ok(Reply::new_const(USAGE, invocation.reply()));
+    }
+    Ok(Reply::new(describe(&matches), invocation.reply()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn message(id: u64, day: u32) -> Message {
+        Message::new(
+            id,
+            1,
+            2,
+            Some(3),
+            Utc.with_ymd_and_hms(2023, 1, day, 0, 0, 0).unwrap(),
+            None,
+            None,
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(describe(&[]), "Nothing like that has been posted here");
+        assert_eq!(
+            describe(&[
+                (message(10, 2), 4),
+                (message(11, 5), 0),
+                (message(10, 2), 1)
+            ]),
+            "Distance 0: posted on 2023-01-05 https://discord.com/channels/1/2/11\n\
+            Distance 1: posted on 2023-01-02 https://discord.com/channels/1/2/10"
+        );
+
+        let many = (1..=12)
+            .map(|day| (message(day.into(), day), 2))
+            .collect_vec();
+        let reply = describe(&many);
+        assert_eq!(reply.lines().count(), MAX_MATCHES + 1);
+        assert!(reply.ends_with("...and 2 more"));
+    }
+}
@@ -1,13 +1,141 @@
+mod backfill;
+mod channel;
+mod config;
+mod erase;
+mod export;
+mod filter;
+mod lookup;
+mod optout;
+mod original;
 mod pins;
+mod search;
+mod stats;
 
-use crate::errors::Result;
+use crate::errors::{Error, Result};
+use crate::export::{ExportFormat, ExportKind};
+use crate::handler::settings::{ServerSettings, SettingKey};
 use crate::structs::reply::{Reply, ReplyType};
+use chrono::Utc;
+use db::structs::{ChannelMode, MessageFilter};
 
-use db::{read_only_db_call, ReadOnlyDb};
+use db::{read_only_db_async, ReadOnlyDb};
 use lazy_static::lazy_static;
 use log::warn;
 use regex::Regex;
-use serenity::{model::channel::Message, prelude::*};
+use serenity::{
+    builder::{
+        CreateApplicationCommand, CreateApplicationCommandOption, CreateApplicationCommands,
+    },
+    model::{
+        application::command::CommandOptionType,
+        application::interaction::application_command::{
+            ApplicationCommandInteraction, CommandDataOption,
+        },
+        channel::{ChannelType, Message},
+        id::{ChannelId, GuildId, MessageId},
+        permissions::Permissions,
+        user::User,
+    },
+    prelude::*,
+};
+
+pub(crate) use filter::parse_filter;
+
+// admin and personal commands, slash command responses to these are only shown to
+// the person who ran them
+const EPHEMERAL_COMMANDS: [&str; 7] = [
+    "config", "channel", "optout", "optin", "erase", "backfill", "export",
+];
+
+// order option values are passed to commands in, matching how they're typed out after !rpm
+const OPTION_ORDER: [&str; 13] = [
+    "key", "value", "mode", "channel", "id", "user", "url", "terms", "format", "window", "from",
+    "to", "page",
+];
+
+// options that are typed out as name:value after !rpm
+const NAMED_OPTIONS: [&str; 3] = ["from", "to", "page"];
+
+// threads are stored like any other channel, so they can be picked anywhere a channel can
+const CHANNEL_TYPES: &[ChannelType] = &[
+    ChannelType::Text,
+    ChannelType::News,
+    ChannelType::NewsThread,
+    ChannelType::Forum,
+    ChannelType::PublicThread,
+    ChannelType::PrivateThread,
+];
+
+/// Where a command came from, either a `!rpm` message or a slash command
+#[derive(Debug, Copy, Clone)]
+pub enum Invocation<'a> {
+    Message(&'a Message),
+    Interaction(&'a ApplicationCommandInteraction),
+}
+
+impl<'a> Invocation<'a> {
+    fn guild_id(&self) -> Result<GuildId> {
+        match self {
+            Invocation::Message(msg) => msg.guild_id,
+            Invocation::Interaction(interaction) => interaction.guild_id,
+        }
+        .ok_or(Error::ConstStr("Command wasn't run in a server"))
+    }
+
+    const fn author(&self) -> &'a User {
+        match *self {
+            Invocation::Message(msg) => &msg.author,
+            Invocation::Interaction(interaction) => &interaction.user,
+        }
+    }
+
+    const fn channel_id(&self) -> ChannelId {
+        match self {
+            Invocation::Message(msg) => msg.channel_id,
+            Invocation::Interaction(interaction) => interaction.channel_id,
+        }
+    }
+
+    /// The message the command was in, slash commands don't have one
+    const fn message_id(&self) -> Option<MessageId> {
+        match self {
+            Invocation::Message(msg) => Some(msg.id),
+            Invocation::Interaction(_) => None,
+        }
+    }
+
+    /// Reply directed at the person that ran the command
+    const fn reply(&self) -> ReplyType<'a> {
+        match *self {
+            Invocation::Message(msg) => ReplyType::Message(msg),
+            Invocation::Interaction(interaction) => ReplyType::Interaction(interaction),
+        }
+    }
+
+    /// Reply for the whole channel, for slash commands this still has to be the response
+    const fn channel_reply(&self) -> ReplyType<'a> {
+        match *self {
+            Invocation::Message(msg) => ReplyType::Channel(msg.channel_id),
+            Invocation::Interaction(interaction) => ReplyType::Interaction(interaction),
+        }
+    }
+}
+
+/// Returns true if whoever ran the command has the Manage Server permission
+async fn is_server_manager(ctx: &Context, invocation: Invocation<'_>) -> Result<bool> {
+    match invocation {
+        Invocation::Message(msg) => {
+            let member = msg.member(ctx).await?;
+            Ok(member.permissions(ctx)?.manage_guild())
+        }
+        // discord resolves the member's permissions in the channel for us
+        Invocation::Interaction(interaction) => Ok(interaction
+            .member
+            .as_ref()
+            .and_then(|member| member.permissions)
+            .map_or(false, |permissions| permissions.manage_guild())),
+    }
+}
 
 pub(super) fn has_command_prefix(command: &str) -> bool {
     lazy_static! {
@@ -16,36 +144,111 @@ pub(super) fn has_command_prefix(command: &str) -> bool {
     RE.is_match(command)
 }
 
-fn repost_cnt(msg: &Message) -> Result<Reply<'_>> {
-    let reposts = read_only_db_call(|db| db.get_repost_list(*msg.guild_id.unwrap().as_u64()))
+/// Takes a `page N` (or `page:N` from slash commands) out of the args, returning the page
+/// and the rest of the args. Defaults to the first page, None if the page isn't a number.
+fn split_page<'b>(args: &[&'b str]) -> Option<(usize, Vec<&'b str>)> {
+    let mut page = 1;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg.eq_ignore_ascii_case("page") {
+            page = args.next()?.parse().ok()?;
+        } else if let Some(number) = arg.strip_prefix("page:") {
+            page = number.parse().ok()?;
+        } else {
+            rest.push(*arg);
+        }
+    }
+    Some((page, rest))
+}
+
+/// The page and filter for the leaderboards, see split_page and parse_filter
+fn leaderboard_args(args: &[&str]) -> Option<(usize, MessageFilter)> {
+    let (page, args) = split_page(args)?;
+    Some((page, parse_filter(&args, Utc::now())?))
+}
+
+async fn repost_cnt<'a>(invocation: Invocation<'a>, args: &[&str]) -> Result<Reply<'a>> {
+    let (page, filter) = match leaderboard_args(args) {
+        Some(args) => args,
+        None => {
+            return Ok(Reply::new_const(
+                "Usage: !rpm reposts [today|week|month|year] [from:YYYY-MM-DD] [to:YYYY-MM-DD] [#channel] [page N]",
+                invocation.reply(),
+            ))
+        }
+    };
+    let server_id = *invocation.guild_id()?.as_u64();
+    let limit = ServerSettings::load(server_id).await?.leaderboard_size;
+    let reposts = read_only_db_async(move |db| db.get_repost_list(server_id, filter, limit))
+        .await
         .map_or_else(|_| Vec::new(), |r| r);
 
-    let response = format!(
-        "Count | Link\n{}",
-        reposts
-            .into_iter()
-            .map(|x| format!("{:<9} | <{}>", x.count, x.link))
-            .collect::<Vec<String>>()
-            .join("\n")
-    );
+    let lines = reposts
+        .into_iter()
+        .map(|x| format!("{:<9} | <{}>", x.count, x.link))
+        .collect::<Vec<String>>();
 
-    Ok(Reply::new(response, ReplyType::Channel(msg.channel_id)))
+    Ok(Reply::new_page(
+        "Count | Link",
+        &lines,
+        page,
+        invocation.channel_reply(),
+    ))
 }
 
-fn reposter_cnt(msg: &Message) -> Result<Reply<'_>> {
-    let reposters = read_only_db_call(|db| db.get_top_reposters(*msg.guild_id.unwrap().as_u64()))
+async fn reposter_cnt<'a>(invocation: Invocation<'a>, args: &[&str]) -> Result<Reply<'a>> {
+    let (page, filter) = match leaderboard_args(args) {
+        Some(args) => args,
+        None => {
+            return Ok(Reply::new_const(
+                "Usage: !rpm reposters [today|week|month|year] [from:YYYY-MM-DD] [to:YYYY-MM-DD] [#channel] [page N]",
+                invocation.reply(),
+            ))
+        }
+    };
+    let server_id = *invocation.guild_id()?.as_u64();
+    let limit = ServerSettings::load(server_id).await?.leaderboard_size;
+    let reposters = read_only_db_async(move |db| db.get_top_reposters(server_id, filter, limit))
+        .await
         .map_or_else(|_| Vec::new(), |r| r);
 
-    let response = format!(
-        "Username | Count\n{}",
-        reposters
-            .into_iter()
-            .map(|x| format!("{} | {:<9}", x.username, x.count))
-            .collect::<Vec<String>>()
-            .join("\n")
-    );
+    let lines = reposters
+        .into_iter()
+        .map(|x| format!("{} | {:<9}", x.username, x.count))
+        .collect::<Vec<String>>();
+
+    Ok(Reply::new_page(
+        "Username | Count",
+        &lines,
+        page,
+        invocation.channel_reply(),
+    ))
+}
 
-    Ok(Reply::new(response, ReplyType::Channel(msg.channel_id)))
+async fn run_command<'a>(
+    ctx: &Context,
+    invocation: Invocation<'a>,
+    command: &str,
+    args: &[&str],
+) -> Result<Reply<'a>> {
+    match command {
+        "pins" => pins::pins(ctx, invocation, args).await,
+        "reposts" => repost_cnt(invocation, args).await,
+        "reposters" => reposter_cnt(invocation, args).await,
+        "stats" => stats::stats(invocation, args).await,
+        "original" => original::original(ctx, invocation, args).await,
+        "lookup" => lookup::lookup(invocation, args).await,
+        "search" => search::search(invocation, args).await,
+        "config" => config::config(ctx, invocation, args).await,
+        "channel" => channel::channel(ctx, invocation, args).await,
+        "optout" => optout::optout(invocation).await,
+        "optin" => optout::optin(invocation).await,
+        "erase" => erase::erase(ctx, invocation, args).await,
+        "backfill" => backfill::backfill(ctx, invocation, args).await,
+        "export" => export::export(ctx, invocation, args).await,
+        _ => Ok(Reply::new_const("Unrecognized command", invocation.reply())),
+    }
 }
 
 pub async fn handle_command<'a>(ctx: &Context, msg: &'a Message) -> Option<Reply<'a>> {
@@ -55,15 +258,14 @@ pub async fn handle_command<'a>(ctx: &Context, msg: &'a Message) -> Option<Reply
     }
 
     let command = &msg.content[4..].trim();
-    let ret = match *command {
-        "pins" => pins::pins(ctx, msg).await,
-        "reposts" => repost_cnt(msg),
-        "reposters" => reposter_cnt(msg),
-        _ => Ok(Reply::new_const(
-            "Unrecognized command",
-            ReplyType::Message(msg),
-        )),
-    };
+    let args = command.split_whitespace().collect::<Vec<&str>>();
+    let ret = run_command(
+        ctx,
+        Invocation::Message(msg),
+        args.first().copied().unwrap_or_default(),
+        args.get(1..).unwrap_or_default(),
+    )
+    .await;
 
     match ret {
         Ok(resp) => Some(resp),
@@ -74,6 +276,342 @@ pub async fn handle_command<'a>(ctx: &Context, msg: &'a Message) -> Option<Reply
     }
 }
 
+/// Flattens slash command options into the same args the text command would get, so
+/// subcommands come first followed by the option values in OPTION_ORDER
+fn interaction_args(mut options: &[CommandDataOption]) -> Vec<String> {
+    let mut args = Vec::new();
+    while let Some(sub) = options.first().filter(|o| {
+        o.kind == CommandOptionType::SubCommand || o.kind == CommandOptionType::SubCommandGroup
+    }) {
+        args.push(sub.name.clone());
+        options = &sub.options;
+    }
+
+    for name in OPTION_ORDER {
+        let value = options
+            .iter()
+            .find(|o| o.name == name)
+            .and_then(|o| o.value.as_ref());
+        let value = match value {
+            Some(serde_json::Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => continue,
+        };
+        if NAMED_OPTIONS.contains(&name) {
+            args.push(format!("{name}:{value}"));
+        } else {
+            args.push(value);
+        }
+    }
+    args
+}
+
+pub async fn handle_interaction(
+    ctx: &Context,
+    interaction: &ApplicationCommandInteraction,
+) -> Result<()> {
+    let command = interaction.data.name.as_str();
+    // some commands take a while, so always defer and edit the response in after
+    if EPHEMERAL_COMMANDS.contains(&command) {
+        interaction.defer_ephemeral(ctx).await?;
+    } else {
+        interaction.defer(ctx).await?;
+    }
+
+    let args = interaction_args(&interaction.data.options);
+    let args = args.iter().map(String::as_str).collect::<Vec<&str>>();
+    match run_command(ctx, Invocation::Interaction(interaction), command, &args).await {
+        Ok(reply) => reply.send(ctx).await,
+        Err(why) => {
+            warn!("Failed to process slash command {command} with err: {why}");
+            interaction
+                .edit_original_interaction_response(ctx, |r| r.content("Failed to run command"))
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+fn setting_key_option(required: bool) -> CreateApplicationCommandOption {
+    let mut option = CreateApplicationCommandOption::default();
+    option
+        .name("key")
+        .description("Setting to use")
+        .kind(CommandOptionType::String)
+        .required(required);
+    for key in SettingKey::ALL {
+        option.add_string_choice(key.name(), key.name());
+    }
+    option
+}
+
+/// Options for limiting the leaderboards to a time window or channel, see parse_filter
+fn add_filter_options(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .create_option(|o| {
+            o.name("window")
+                .description("Only count recent reposts")
+                .kind(CommandOptionType::String);
+            for window in ["today", "week", "month", "year"] {
+                o.add_string_choice(window, window);
+            }
+            o
+        })
+        .create_option(|o| {
+            o.name("channel")
+                .description("Only count reposts in this channel")
+                .kind(CommandOptionType::Channel)
+                .channel_types(CHANNEL_TYPES)
+        })
+        .create_option(|o| {
+            o.name("from")
+                .description("Only count reposts sent on or after YYYY-MM-DD")
+                .kind(CommandOptionType::String)
+        })
+        .create_option(|o| {
+            o.name("to")
+                .description("Only count reposts sent on or before YYYY-MM-DD")
+                .kind(CommandOptionType::String)
+        })
+        .create_option(page_option)
+}
+
+/// Option for which page of a long list to show
+fn page_option(option: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+    option
+        .name("page")
+        .description("Page to show, starting from 1")
+        .kind(CommandOptionType::Integer)
+        .min_int_value(1)
+}
+
+/// Adds every command as a slash command
+pub fn register_commands(
+    commands: &mut CreateApplicationCommands,
+) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|c| {
+            c.name("pins")
+                .description("Who has the most pinned messages")
+                .dm_permission(false)
+                .create_option(page_option)
+        })
+        .create_application_command(|c| {
+            c.name("reposts")
+                .description("Most reposted links")
+                .dm_permission(false);
+            add_filter_options(c)
+        })
+        .create_application_command(|c| {
+            c.name("reposters")
+                .description("Users with the most reposts")
+                .dm_permission(false);
+            add_filter_options(c)
+        })
+        .create_application_command(|c| {
+            c.name("stats")
+                .description("How much someone has posted and been reposted")
+                .dm_permission(false)
+                .create_option(|o| {
+                    o.name("user")
+                        .description("Defaults to you")
+                        .kind(CommandOptionType::User)
+                })
+        })
+        .create_application_command(|c| {
+            c.name("original")
+                .description("Who posted a link first, without posting it yourself")
+                .dm_permission(false)
+                .create_option(|o| {
+                    o.name("url")
+                        .description("Link to look up")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+        })
+        .create_application_command(|c| {
+            c.name("lookup")
+                .description("Find images like this one, without posting it yourself")
+                .dm_permission(false)
+                .create_option(|o| {
+                    o.name("url")
+                        .description("Link to the image")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+        })
+        .create_application_command(|c| {
+            c.name("search")
+                .description("Recent posts of links with these words in them")
+                .dm_permission(false)
+                .create_option(|o| {
+                    o.name("terms")
+                        .description("Words in the link, e.g. bbc news")
+                        .kind(CommandOptionType::String)
+                        .required(true)
+                })
+        })
+        .create_application_command(|c| {
+            c.name("optout")
+                .description("Stop the bot storing your messages and delete what it has stored")
+        })
+        .create_application_command(|c| {
+            c.name("optin")
+                .description("Let the bot store and check your messages again")
+        })
+        .create_application_command(|c| {
+            c.name("erase")
+                .description("Delete everything stored for a user or server, bot owner only")
+                .default_member_permissions(Permissions::ADMINISTRATOR)
+                .create_option(|o| {
+                    o.name("user")
+                        .description("Erase a user")
+                        .kind(CommandOptionType::SubCommand)
+                        .create_sub_option(|i| {
+                            i.name("id")
+                                .description("User id")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                })
+                .create_option(|o| {
+                    o.name("server")
+                        .description("Erase a server")
+                        .kind(CommandOptionType::SubCommand)
+                        .create_sub_option(|i| {
+                            i.name("id")
+                                .description("Server id")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                })
+        })
+        .create_application_command(|c| {
+            c.name("config")
+                .description("View or change the bot's settings for this server")
+                .dm_permission(false)
+                .default_member_permissions(Permissions::MANAGE_GUILD)
+                .create_option(|o| {
+                    o.name("get")
+                        .description("Show the current value of settings")
+                        .kind(CommandOptionType::SubCommand)
+                        .add_sub_option(setting_key_option(false))
+                })
+                .create_option(|o| {
+                    o.name("set")
+                        .description("Change a setting")
+                        .kind(CommandOptionType::SubCommand)
+                        .add_sub_option(setting_key_option(true))
+                        .create_sub_option(|v| {
+                            v.name("value")
+                                .description("New value, lists are comma separated")
+                                .kind(CommandOptionType::String)
+                                .required(true)
+                        })
+                })
+                .create_option(|o| {
+                    o.name("reset")
+                        .description("Set a setting back to the default")
+                        .kind(CommandOptionType::SubCommand)
+                        .add_sub_option(setting_key_option(true))
+                })
+        })
+        .create_application_command(|c| {
+            c.name("channel")
+                .description("View or change how the bot behaves in a channel")
+                .dm_permission(false)
+                .default_member_permissions(Permissions::MANAGE_GUILD)
+                .create_option(|o| {
+                    o.name("mode")
+                        .description("Leave out to list channels not using the default")
+                        .kind(CommandOptionType::String);
+                    for mode in ChannelMode::ALL {
+                        o.add_string_choice(mode, mode);
+                    }
+                    o
+                })
+                .create_option(|o| {
+                    o.name("channel")
+                        .description("Channel to change, defaults to this one")
+                        .kind(CommandOptionType::Channel)
+                        .channel_types(CHANNEL_TYPES)
+                })
+        })
+        .create_application_command(|c| {
+            c.name("backfill")
+                .description("Load the history of channels from before the bot joined")
+                .dm_permission(false)
+                .default_member_permissions(Permissions::MANAGE_GUILD);
+            for (name, description) in [
+                ("status", "Show how far each channel has been loaded"),
+                (
+                    "start",
+                    "Start or resume loading, leave out the channel for all of them",
+                ),
+                (
+                    "pause",
+                    "Pause loading, leave out the channel for all of them",
+                ),
+                ("prioritise", "Load a channel before any others"),
+            ] {
+                c.create_option(|o| {
+                    o.name(name)
+                        .description(description)
+                        .kind(CommandOptionType::SubCommand);
+                    if name != "status" {
+                        o.create_sub_option(|i| {
+                            i.name("channel")
+                                .description("Channel to backfill")
+                                .kind(CommandOptionType::Channel)
+                                .channel_types(CHANNEL_TYPES)
+                        });
+                    }
+                    o
+                });
+            }
+            c
+        })
+        .create_application_command(|c| {
+            c.name("export")
+                .description("Download this server's repost history as a CSV or JSON file")
+                .dm_permission(false)
+                .default_member_permissions(Permissions::MANAGE_GUILD);
+            for kind in ExportKind::ALL {
+                c.create_option(|o| {
+                    o.name(kind.name())
+                        .description(kind.description())
+                        .kind(CommandOptionType::SubCommand)
+                        .create_sub_option(|i| {
+                            i.name("format")
+                                .description("File format, defaults to CSV")
+                                .kind(CommandOptionType::String);
+                            for format in [ExportFormat::Csv, ExportFormat::Json] {
+                                i.add_string_choice(format.extension(), format.extension());
+                            }
+                            i
+                        })
+                        .create_sub_option(|i| {
+                            i.name("channel")
+                                .description("Only export messages in this channel")
+                                .kind(CommandOptionType::Channel)
+                                .channel_types(CHANNEL_TYPES)
+                        })
+                        .create_sub_option(|i| {
+                            i.name("from")
+                                .description("Only export messages sent on or after YYYY-MM-DD")
+                                .kind(CommandOptionType::String)
+                        })
+                        .create_sub_option(|i| {
+                            i.name("to")
+                                .description("Only export messages sent on or before YYYY-MM-DD")
+                                .kind(CommandOptionType::String)
+                        })
+                });
+            }
+            c
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -113,4 +651,58 @@ mod tests {
         assert!(!has_command_prefix("!"));
         assert!(!has_command_prefix("hello world!"));
     }
+
+    #[test]
+    fn test_interaction_args() {
+        // options as discord sends them for /config set value:5 key:image_distance
+        let options: Vec<CommandDataOption> = serde_json::from_value(serde_json::json!([{
+            "name": "set",
+            "type": 1,
+            "options": [
+                {"name": "value", "type": 3, "value": "5"},
+                {"name": "key", "type": 3, "value": "image_distance"},
+            ]
+        }]))
+        .unwrap();
+        assert_eq!(
+            interaction_args(&options),
+            vec!["set", "image_distance", "5"]
+        );
+        assert!(interaction_args(&[]).is_empty());
+
+        // dates are named so either can be left out
+        let options: Vec<CommandDataOption> = serde_json::from_value(serde_json::json!([{
+            "name": "links",
+            "type": 1,
+            "options": [
+                {"name": "to", "type": 3, "value": "2023-01-31"},
+                {"name": "format", "type": 3, "value": "json"},
+            ]
+        }]))
+        .unwrap();
+        assert_eq!(
+            interaction_args(&options),
+            vec!["links", "json", "to:2023-01-31"]
+        );
+
+        let options: Vec<CommandDataOption> = serde_json::from_value(serde_json::json!([
+            {"name": "page", "type": 4, "value": 2},
+            {"name": "window", "type": 3, "value": "week"},
+        ]))
+        .unwrap();
+        assert_eq!(interaction_args(&options), vec!["week", "page:2"]);
+    }
+
+    #[test]
+    fn test_split_page() {
+        assert_eq!(split_page(&[]), Some((1, vec![])));
+        assert_eq!(
+            split_page(&["week", "Page", "3", "#memes"]),
+            Some((3, vec!["week", "#memes"]))
+        );
+        assert_eq!(split_page(&["page:2", "month"]), Some((2, vec!["month"])));
+        assert_eq!(split_page(&["page"]), None);
+        assert_eq!(split_page(&["page", "two"]), None);
+        assert_eq!(split_page(&["page:-1"]), None);
+    }
 }
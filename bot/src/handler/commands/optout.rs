@@ -0,0 +1,44 @@
+use super::Invocation;
+use crate::errors::Result;
+use crate::structs::reply::Reply;
+
+use db::{writable_db_async, WriteableDb};
+
+pub async fn optout(invocation: Invocation<'_>) -> Result<Reply<'_>> {
+    let author = invocation.author();
+    let (id, name, bot, discriminator) = (
+        *author.id.as_u64(),
+        author.name.clone(),
+        author.bot,
+        author.discriminator,
+    );
+    writable_db_async(move |mut db| {
+        // user might not have posted anything we've seen yet
+        db.add_user(id, &name, bot, discriminator)?;
+        db.opt_out_user(id)
+    })
+    .await?;
+
+    // the command message itself was just deleted from the db, so don't reply to it directly
+    Ok(Reply::new(
+        format!(
+            "{} opted out, your messages have been deleted from the bot and won't be stored or checked. Use `!rpm optin` to undo",
+            author.name
+        ),
+        invocation.channel_reply(),
+    ))
+}
+
+pub async fn optin(invocation: Invocation<'_>) -> Result<Reply<'_>> {
+    let author = invocation.author();
+    let id = *author.id.as_u64();
+    writable_db_async(move |db| db.opt_in_user(id)).await?;
+
+    Ok(Reply::new(
+        format!(
+            "{} opted in, new messages will be checked for reposts again",
+            author.name
+        ),
+        invocation.channel_reply(),
+    ))
+}
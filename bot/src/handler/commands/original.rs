@@ -0,0 +1,105 @@
+use super::Invocation;
+use crate::errors::Result;
+use crate::handler::links::filtered_url;
+use crate::structs::reply::Reply;
+
+use db::structs::Link;
+use db::{read_only_db_async, ReadOnlyDb};
+use itertools::Itertools;
+use serenity::{model::id::UserId, prelude::*};
+
+const USAGE: &str = "Usage: !rpm original <url>, wrap the url in <> to stop it embedding";
+
+fn describe(url: &str, links: &[Link], first_poster: &str) -> String {
+    let first = match links.iter().min_by_key(|link| link.message.created_at) {
+        Some(first) => first,
+        None => return format!("Nobody has posted <{url}> yet"),
+    };
+    let shares = links.iter().map(|link| link.message.id).unique().count();
+    format!(
+        "<{url}> was first posted by {first_poster} on {} {}\nIt's been posted {shares} time{}",
+        first.message.created_at.format("%Y-%m-%d"),
+        first.message.uri(),
+        if shares == 1 { "" } else { "s" }
+    )
+}
+
+/// Looks up who posted a link first without the link itself being stored, so checking
+/// doesn't count as a repost
+pub async fn original<'a>(
+    ctx: &Context,
+    invocation: Invocation<'a>,
+    args: &[&str],
+) -> Result<Reply<'a>> {
+    let url = match args {
+        [url] => url.trim_start_matches('<').trim_end_matches('>'),
+        _ => return Ok(Reply::new_const(USAGE, invocation.reply())),
+    };
+    let url = match filtered_url(url) {
+        Ok(url) => url.to_string(),
+        Err(_) => return Ok(Reply::new_const(USAGE, invocation.reply())),
+    };
+
+    let server_id = *invocation.guild_id()?.as_u64();
+    let current_id = invocation.message_id().map_or(0, |id| *id.as_u64());
+    let query = url.clone();
+    let links = read_only_db_async(move |db| db.query_links(&query, server_id, current_id)).await?;
+
+    let first_author = links
+        .iter()
+        .min_by_key(|link| link.message.created_at)
+        .and_then(|link| link.message.author);
+    let first_poster = match first_author {
+        Some(id) => UserId(id)
+            .to_user(ctx)
+            .await
+            .map_or_else(|_| String::from("someone"), |user| user.name),
+        None => String::from("someone"),
+    };
+    Ok(Reply::new(
+        describe(&url, &links, &first_poster),
+        invocation.reply(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use db::structs::Message;
+
+    fn link(id: u64, day: u32) -> Link {
+        Link {
+            id: Some(1),
+            link: String::from("https://example.com/"),
+            message: Message::new(
+                id,
+                1,
+                2,
+                Some(3),
+                Utc.with_ymd_and_hms(2023, 1, day, 0, 0, 0).unwrap(),
+                None,
+                None,
+                None,
+                None,
+            ),
+            channel_name: None,
+            server_name: None,
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        let url = "https://example.com/";
+        assert_eq!(
+            describe(url, &[], "user"),
+            "Nobody has posted <https://example.com/> yet"
+        );
+        assert_eq!(
+            describe(url, &[link(11, 5), link(10, 2), link(12, 9)], "user"),
+            "<https://example.com/> was first posted by user on 2023-01-02 https://discord.com/channels/1/2/10\n\
+            It's been posted 3 times"
+        );
+        assert!(describe(url, &[link(10, 2)], "user").ends_with("posted 1 time"));
+    }
+}
@@ -1,22 +1,46 @@
+use super::{split_page, Invocation};
 use crate::errors::Result;
-use crate::handler::bot_read_channel_permission;
-use crate::structs::reply::{Reply, ReplyType};
+use crate::handler::{bot_read_channel_permission, threads};
+use crate::structs::reply::Reply;
 
 use log::trace;
 use serenity::{model::channel::ChannelType, model::channel::Message, prelude::*};
 use std::collections::HashMap;
 
-pub async fn pins<'a>(ctx: &Context, msg: &'a Message) -> Result<Reply<'a>> {
-    let guild = msg.guild_id.unwrap();
+pub async fn pins<'a>(
+    ctx: &Context,
+    invocation: Invocation<'a>,
+    args: &[&str],
+) -> Result<Reply<'a>> {
+    let page = match split_page(args) {
+        Some((page, _)) => page,
+        None => {
+            return Ok(Reply::new_const(
+                "Usage: !rpm pins [page N]",
+                invocation.reply(),
+            ))
+        }
+    };
+    let guild = invocation.guild_id()?;
 
     let channels = guild.channels(&ctx.http).await?;
     let mut pins = Vec::<Message>::new();
     for (_, channel) in channels.iter() {
         let visible = bot_read_channel_permission(&ctx, channel).await;
-        if visible && channel.kind == ChannelType::Text {
+        if visible && matches!(channel.kind, ChannelType::Text | ChannelType::News) {
             pins.extend(channel.pins(&ctx.http).await?);
         }
     }
+    // active threads and forum posts aren't listed with the channels
+    let active_threads = ctx
+        .cache
+        .guild_field(guild, |g| g.threads.clone())
+        .unwrap_or_default();
+    for thread in active_threads {
+        if threads::thread_visible(ctx, &thread).await {
+            pins.extend(thread.pins(&ctx.http).await?);
+        }
+    }
 
     let mut pin_cnt: HashMap<String, usize> = HashMap::new();
     for pin in pins {
@@ -38,14 +62,15 @@ pub async fn pins<'a>(ctx: &Context, msg: &'a Message) -> Result<Reply<'a>> {
     tuples.reverse();
     trace!("found the following pins {tuples:?}");
 
-    let response = format!(
-        "the chamPIoNship\n{}",
-        tuples
-            .into_iter()
-            .map(|x| format!("{}: with {} pins", x.0, x.1))
-            .collect::<Vec<String>>()
-            .join("\n")
-    );
+    let lines = tuples
+        .into_iter()
+        .map(|x| format!("{}: with {} pins", x.0, x.1))
+        .collect::<Vec<String>>();
 
-    Ok(Reply::new(response, ReplyType::Channel(msg.channel_id)))
+    Ok(Reply::new_page(
+        "the chamPIoNship",
+        &lines,
+        page,
+        invocation.channel_reply(),
+    ))
 }
@@ -0,0 +1,78 @@
+use super::Invocation;
+use crate::errors::Result;
+use crate::structs::reply::Reply;
+
+use db::structs::Link;
+use db::{read_only_db_async, ReadOnlyDb};
+use itertools::Itertools;
+
+const USAGE: &str = "Usage: !rpm search <terms>, e.g. !rpm search bbc news";
+
+// keeps the reply well under discord's message length limit
+const MAX_RESULTS: u64 = 10;
+
+fn describe(terms: &str, links: &[Link]) -> String {
+    if links.is_empty() {
+        return format!("No links matching \"{terms}\" have been posted");
+    }
+    links
+        .iter()
+        .map(|link| {
+            format!(
+                "<{}> on {} {}",
+                link.link,
+                link.message.created_at.format("%Y-%m-%d"),
+                link.message.uri()
+            )
+        })
+        .join("\n")
+}
+
+/// Finds the most recent posts of links with the search terms in them
+pub async fn search<'a>(invocation: Invocation<'a>, args: &[&str]) -> Result<Reply<'a>> {
+    if args.is_empty() {
+        return Ok(Reply::new_const(USAGE, invocation.reply()));
+    }
+    let terms = args.join(" ");
+    let server_id = *invocation.guild_id()?.as_u64();
+    let query = terms.clone();
+    let links =
+        read_only_db_async(move |db| db.search_links(server_id, &query, MAX_RESULTS)).await?;
+    Ok(Reply::new(describe(&terms, &links), invocation.reply()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use db::structs::Message;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe("bbc news", &[]),
+            "No links matching \"bbc news\" have been posted"
+        );
+        let link = Link {
+            id: Some(1),
+            link: String::from("https://www.bbc.com/news/article"),
+            message: Message::new(
+                10,
+                1,
+                2,
+                Some(3),
+                Utc.with_ymd_and_hms(2023, 1, 2, 0, 0, 0).unwrap(),
+                None,
+                None,
+                None,
+                None,
+            ),
+            channel_name: None,
+            server_name: None,
+        };
+        assert_eq!(
+            describe("bbc news", &[link]),
+            "<https://www.bbc.com/news/article> on 2023-01-02 https://discord.com/channels/1/2/10"
+        );
+    }
+}
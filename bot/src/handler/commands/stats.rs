@@ -0,0 +1,110 @@
+use super::Invocation;
+use crate::errors::Result;
+use crate::structs::reply::Reply;
+
+use db::structs::UserStats;
+use db::{read_only_db_async, ReadOnlyDb};
+use humantime::format_duration;
+use serenity::model::id::UserId;
+
+const USAGE: &str = "Usage: !rpm stats [@user]";
+
+/// Parses either a user mention from a message or a bare id from a slash command
+fn parse_user(arg: &str) -> Option<UserId> {
+    arg.trim_start_matches("<@")
+        .trim_start_matches('!')
+        .trim_end_matches('>')
+        .parse()
+        .ok()
+        .map(UserId)
+}
+
+fn describe(stats: &UserStats) -> String {
+    let mut lines = vec![
+        format!("Stats for {}:", stats.username),
+        format!("Posted {} links and {} images", stats.links, stats.images),
+        format!("{} of their posts were reposts", stats.reposts),
+        format!(
+            "{} of their posts were reposted by someone else",
+            stats.reposted
+        ),
+    ];
+    if let Some((link, count)) = &stats.top_link {
+        lines.push(format!("Most reposted link: <{link}>, {count} times"));
+    }
+    if let Some(duration) = stats.average_repost_time {
+        lines.push(format!(
+            "Reposted {} after posting on average",
+            format_duration(duration)
+        ));
+    }
+    if let Some((username, count)) = &stats.top_reposter {
+        lines.push(format!("Reposted most by {username}, {count} times"));
+    }
+    lines.join("\n")
+}
+
+pub async fn stats<'a>(invocation: Invocation<'a>, args: &[&str]) -> Result<Reply<'a>> {
+    let user_id = match args {
+        [] => invocation.author().id,
+        [user] => match parse_user(user) {
+            Some(user_id) => user_id,
+            None => return Ok(Reply::new_const(USAGE, invocation.reply())),
+        },
+        _ => return Ok(Reply::new_const(USAGE, invocation.reply())),
+    };
+
+    let (server_id, user_id) = (*invocation.guild_id()?.as_u64(), *user_id.as_u64());
+    let stats = read_only_db_async(move |db| db.get_user_stats(server_id, user_id)).await?;
+    Ok(stats.map_or_else(
+        || {
+            Reply::new_const(
+                "Nothing is stored for that user, they might have opted out",
+                invocation.reply(),
+            )
+        },
+        |stats| Reply::new(describe(&stats), invocation.channel_reply()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_user() {
+        assert_eq!(parse_user("<@1234>"), Some(UserId(1234)));
+        assert_eq!(parse_user("<@!1234>"), Some(UserId(1234)));
+        assert_eq!(parse_user("1234"), Some(UserId(1234)));
+        assert_eq!(parse_user("@someone"), None);
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut stats = UserStats {
+            username: String::from("user"),
+            links: 3,
+            images: 1,
+            reposts: 2,
+            ..UserStats::default()
+        };
+        assert_eq!(
+            describe(&stats),
+            "Stats for user:\n\
+            Posted 3 links and 1 images\n\
+            2 of their posts were reposts\n\
+            0 of their posts were reposted by someone else"
+        );
+
+        stats.reposted = 1;
+        stats.top_link = Some((String::from("https://example.com"), 4));
+        stats.average_repost_time = Some(Duration::from_secs(90 * 60));
+        stats.top_reposter = Some((String::from("other"), 3));
+        assert!(describe(&stats).ends_with(
+            "Most reposted link: <https://example.com>, 4 times\n\
+            Reposted 1h 30m after posting on average\n\
+            Reposted most by other, 3 times"
+        ));
+    }
+}
@@ -0,0 +1,290 @@
+use super::commands::has_command_prefix;
+use super::images::{find_url_matches, ImageProcesser};
+use super::links::get_filtered_links;
+use super::settings::ServerSettings;
+use super::threads;
+use crate::errors::Result;
+use crate::structs::reply::{Reply, ReplyType};
+
+use chrono::{DateTime, Utc};
+use db::{read_only_db_async, ReadOnlyDb};
+use itertools::Itertools;
+use log::{info, warn};
+use serenity::{
+    model::{
+        channel::{ChannelType, Message},
+        guild::Member,
+        id::{ChannelId, GuildId},
+        permissions::Permissions,
+    },
+    prelude::*,
+};
+use std::collections::HashMap;
+
+// keeps the reply well under discord's message length limit
+const MAX_SIGHTINGS: usize = 10;
+
+/// Somewhere a link or image from the DM was already posted
+#[derive(Debug)]
+struct Sighting {
+    /// the link, or None for an image
+    link: Option<String>,
+    /// how far off the image was
+    distance: Option<u32>,
+    server: String,
+    channel: String,
+    created_at: DateTime<Utc>,
+    uri: String,
+}
+
+fn describe(sightings: &[Sighting]) -> String {
+    if sightings.is_empty() {
+        return String::from("That hasn't been posted in any server we share");
+    }
+    let mut lines = sightings
+        .iter()
+        .sorted_by_key(|sighting| sighting.created_at)
+        .take(MAX_SIGHTINGS)
+        .map(|sighting| {
+            format!(
+                "{} was posted in {} #{} on {} {}",
+                sighting.link.as_ref().map_or_else(
+                    || format!(
+                        "An image (distance {})",
+                        sighting.distance.unwrap_or_default()
+                    ),
+                    |link| format!("<{link}>")
+                ),
+                sighting.server,
+                sighting.channel,
+                sighting.created_at.format("%Y-%m-%d"),
+                sighting.uri
+            )
+        })
+        .collect_vec();
+    if sightings.len() > MAX_SIGHTINGS {
+        lines.push(format!("...and {} more", sightings.len() - MAX_SIGHTINGS));
+    }
+    lines.join("\n")
+}
+
+/// Image urls given to `!rpm lookup`, which are checked as images rather than links
+fn lookup_urls(content: &str) -> Option<Vec<String>> {
+    if !has_command_prefix(content) {
+        return None;
+    }
+    let mut args = content[4..].split_whitespace();
+    args.next()
+        .filter(|command| command.eq_ignore_ascii_case("lookup"))?;
+    Some(
+        args.map(|url| {
+            url.trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+        .collect(),
+    )
+}
+
+/// The name of the channel if the member can read it, threads are readable if their parent
+/// is, apart from private threads which are left out entirely
+async fn visible_channel_name(
+    ctx: &Context,
+    server: GuildId,
+    member: &Member,
+    channel_id: u64,
+) -> Option<String> {
+    let (name, channel) = match ctx.cache.guild_channel(channel_id) {
+        Some(channel) => (channel.name.clone(), channel),
+        None => {
+            let thread = threads::get_thread(ctx, server, ChannelId(channel_id))
+                .await
+                .ok()?;
+            if thread.kind == ChannelType::PrivateThread {
+                return None;
+            }
+            (thread.name, ctx.cache.guild_channel(thread.parent_id?)?)
+        }
+    };
+    let permissions = ctx.cache.guild_field(server, |guild| {
+        guild.user_permissions_in(&channel, member).ok()
+    })??;
+    permissions
+        .contains(Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY)
+        .then_some(name)
+}
+
+// a link (or None for an image), how far off the image was and where it was posted
+type Match = (Option<String>, Option<u32>, db::structs::Message);
+
+/// Every link and image in the message that was already posted in the server
+async fn server_matches(
+    msg: &Message,
+    server: GuildId,
+    hashed: &mut HashMap<String, Option<String>>,
+) -> Result<Vec<Match>> {
+    let server_id = *server.as_u64();
+    let msg_id = *msg.id.as_u64();
+    let settings = ServerSettings::load(server_id).await?;
+
+    let mut found = Vec::new();
+    let mut image_matches = match lookup_urls(&msg.content) {
+        Some(urls) => find_url_matches(&urls, msg_id, server_id, &settings, hashed).await?,
+        None => {
+            for link in get_filtered_links(&msg.content, &settings) {
+                let query = link.clone();
+                let links =
+                    read_only_db_async(move |db| db.query_links(&query, server_id, msg_id)).await?;
+                found.extend(
+                    links
+                        .into_iter()
+                        .map(|l| (Some(link.clone()), None, l.message)),
+                );
+            }
+            Vec::new()
+        }
+    };
+    let images = ImageProcesser::new(msg_id, server_id, &msg.attachments, &msg.embeds);
+    image_matches.extend(images.find_matches(&settings, hashed).await?);
+    found.extend(
+        image_matches
+            .into_iter()
+            .map(|(db_msg, distance)| (None, Some(distance), db_msg)),
+    );
+    Ok(found)
+}
+
+/// The matches in channels the member can see
+async fn visible_sightings(
+    ctx: &Context,
+    server: GuildId,
+    member: &Member,
+    found: Vec<Match>,
+) -> Vec<Sighting> {
+    let server_name = server.name(ctx).unwrap_or_default();
+    let mut channels = HashMap::new();
+    let channel_ids = found.iter().map(|(_, _, db_msg)| db_msg.channel).unique();
+    for channel in channel_ids.collect_vec() {
+        if let Some(name) = visible_channel_name(ctx, server, member, channel).await {
+            channels.insert(channel, name);
+        }
+    }
+    let mut sightings = Vec::new();
+    for (link, distance, db_msg) in found {
+        if let Some(channel) = channels.get(&db_msg.channel) {
+            sightings.push(Sighting {
+                link,
+                distance,
+                server: server_name.clone(),
+                channel: channel.clone(),
+                created_at: db_msg.created_at,
+                uri: db_msg.uri(),
+            });
+        }
+    }
+    sightings
+}
+
+/// Checks the links and images in a DM against every server the sender shares with the bot,
+/// without storing any of it. Nothing is replied if the message has neither, or the sender
+/// opted out.
+pub async fn check_direct_message<'a>(
+    ctx: &Context,
+    msg: &'a Message,
+) -> Result<Option<Reply<'a>>> {
+    let has_images = !msg.attachments.is_empty()
+        || !msg.embeds.is_empty()
+        || lookup_urls(&msg.content).map_or(false, |urls| !urls.is_empty());
+    if get_filtered_links(&msg.content, &ServerSettings::default()).is_empty() && !has_images {
+        return Ok(None);
+    }
+    let author_id = *msg.author.id.as_u64();
+    if read_only_db_async(move |db| db.is_opted_out(author_id)).await? {
+        return Ok(None);
+    }
+
+    let mut hashed = HashMap::new();
+    let mut sightings = Vec::new();
+    for server in ctx.cache.guilds() {
+        let found = match server_matches(msg, server, &mut hashed).await {
+            Ok(found) if found.is_empty() => continue,
+            Ok(found) => found,
+            Err(why) => {
+                warn!("failed to check DM {} against {server}: {why:?}", msg.id);
+                continue;
+            }
+        };
+        // only servers with matches need the member, which is fetched if it isn't cached
+        // and errors when the user isn't in the server
+        let member = match ctx.cache.member(server, msg.author.id) {
+            Some(member) => member,
+            None => match server.member(ctx, msg.author.id).await {
+                Ok(member) => member,
+                Err(_) => continue,
+            },
+        };
+        sightings.extend(visible_sightings(ctx, server, &member, found).await);
+    }
+    info!(
+        "DM {} from {} was posted {} times in shared servers",
+        msg.id,
+        msg.author.id,
+        sightings.len()
+    );
+    Ok(Some(Reply::new(
+        describe(&sightings),
+        ReplyType::DirectMessage(msg.id, msg.channel_id),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sighting(link: Option<&str>, day: u32) -> Sighting {
+        Sighting {
+            link: link.map(String::from),
+            distance: link.map_or(Some(3), |_| None),
+            server: String::from("server"),
+            channel: String::from("general"),
+            created_at: Utc.with_ymd_and_hms(2023, 1, day, 0, 0, 0).unwrap(),
+            uri: format!("https://discord.com/channels/1/2/{day}"),
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe(&[]),
+            "That hasn't been posted in any server we share"
+        );
+        assert_eq!(
+            describe(&[
+                sighting(None, 9),
+                sighting(Some("https://example.com/"), 2)
+            ]),
+            "<https://example.com/> was posted in server #general on 2023-01-02 https://discord.com/channels/1/2/2\n\
+            An image (distance 3) was posted in server #general on 2023-01-09 https://discord.com/channels/1/2/9"
+        );
+
+        let many = (1..=12).map(|day| sighting(None, day)).collect_vec();
+        let reply = describe(&many);
+        assert_eq!(reply.lines().count(), MAX_SIGHTINGS + 1);
+        assert!(reply.ends_with("...and 2 more"));
+    }
+
+    #[test]
+    fn test_lookup_urls() {
+        assert_eq!(
+            lookup_urls("!rpm lookup <https://example.com/a.png> https://example.com/b.png"),
+            Some(vec![
+                String::from("https://example.com/a.png"),
+                String::from("https://example.com/b.png")
+            ])
+        );
+        assert_eq!(lookup_urls("!rpm lookup"), Some(Vec::new()));
+        assert_eq!(lookup_urls("!rpm original https://example.com/"), None);
+        assert_eq!(lookup_urls("https://example.com/"), None);
+    }
+}
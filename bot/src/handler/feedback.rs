@@ -0,0 +1,54 @@
+use super::replies::delete_reply;
+use super::settings::ServerSettings;
+use crate::errors::Result;
+
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
+use log::info;
+use serenity::{
+    model::{
+        channel::Reaction,
+        id::{GuildId, MessageId},
+    },
+    prelude::*,
+};
+
+/// Handles someone reacting to one of our replies to say it isn't actually a repost. The
+/// reposts are recorded as false positives, so they're no longer found or counted, and
+/// the reply is deleted. Reactions from whoever reposted are ignored, as they could otherwise
+/// clear their own reposts, unless they manage the server.
+pub async fn check_not_repost_reaction(ctx: &Context, reaction: &Reaction) -> Result<()> {
+    let (server_id, user_id) = match (reaction.guild_id, reaction.user_id) {
+        (Some(server_id), Some(user_id)) => (*server_id.as_u64(), user_id),
+        _ => return Ok(()),
+    };
+    if user_id == ctx.cache.current_user_id() {
+        return Ok(());
+    }
+
+    let reply_id = *reaction.message_id.as_u64();
+    let reply = match read_only_db_async(move |db| db.get_reply_by_id(reply_id)).await? {
+        Some(reply) => reply,
+        None => return Ok(()),
+    };
+    if !ServerSettings::load(server_id)
+        .await?
+        .is_not_repost_emoji(&reaction.emoji)
+    {
+        return Ok(());
+    }
+
+    let (replied_to, reported_by) = (reply.replied_to, *user_id.as_u64());
+    let reposter = read_only_db_async(move |db| db.get_message(MessageId(replied_to)))
+        .await?
+        .and_then(|msg| msg.author);
+    if reposter == Some(reported_by) {
+        let member = GuildId(server_id).member(ctx, user_id).await?;
+        if !member.permissions(ctx)?.manage_guild() {
+            info!("ignoring {user_id} marking their own repost {replied_to} as not a repost");
+            return Ok(());
+        }
+    }
+    info!("{user_id} marked reply {reply_id} to message {replied_to} as not a repost");
+    writable_db_async(move |db| db.add_false_positives(replied_to, reported_by)).await?;
+    delete_reply(ctx, &reply).await
+}
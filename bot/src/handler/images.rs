@@ -1,23 +1,21 @@
+use super::settings::ServerSettings;
 use crate::errors::{Error, Result};
 use crate::structs::repost::{RepostSet, RepostType};
 
-use db::{get_read_only_db, writable_db_call, ReadOnlyDb, WriteableDb};
+use db::structs::RepostEvent;
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
 use image::error::ImageError;
 use image::io::Reader;
 use log::{info, warn};
-use phf::phf_set;
 use serenity::model::channel::{Attachment, Embed};
 use serenity::model::prelude::{EmbedThumbnail, Message};
+use std::collections::HashMap;
+use std::fs;
 use std::io::Cursor;
+use std::path::PathBuf;
 use std::time::Instant;
 use visual_hash::{HashAlg, HasherConfig, ImageHash};
 
-static IGNORED_PROVIDERS: phf::Set<&'static str> = phf_set! {
-    "Tenor",
-    "YouTube",
-    "Apple Music",
-};
-
 #[derive(Debug)]
 pub struct ImageProcesser<'a> {
     msg_id: u64,
@@ -42,6 +40,58 @@ impl<'a> ImageProcesser<'a> {
     }
 }
 
+/// An image in a message that should be checked, either attached or in an embed
+#[derive(Debug)]
+enum ImageSource<'a> {
+    Attachment(&'a Attachment),
+    Embed {
+        url: &'a String,
+        proxy_url: Option<&'a String>,
+    },
+    /// Already downloaded, i.e. from an export, stored under the url it had on discord
+    File {
+        url: String,
+        path: PathBuf,
+    },
+}
+
+impl ImageSource<'_> {
+    const fn url(&self) -> &String {
+        match self {
+            ImageSource::Attachment(attachment) => &attachment.url,
+            ImageSource::Embed { url, .. } => url,
+            ImageSource::File { url, .. } => url,
+        }
+    }
+
+    async fn hash(&self, msg_id: u64) -> Result<Option<ImageHash>> {
+        match self {
+            ImageSource::Attachment(attachment) => {
+                let download_time = Instant::now();
+                // need to actually handle download failures at some pointc
+                let bytes = attachment.download().await?;
+                warn!(
+                    "msg {msg_id} has attachment with {} bytes downloaded in {:.2?}",
+                    bytes.len(),
+                    download_time.elapsed()
+                );
+                let parse_time = Instant::now();
+                let hash = get_image_hash(&bytes)?;
+                if let Some(hash) = &hash {
+                    warn!(
+                        "msg {msg_id} has attachment with hash {} parsed in {:.2?}",
+                        hash.to_base64(),
+                        parse_time.elapsed()
+                    );
+                }
+                Ok(hash)
+            }
+            ImageSource::Embed { url, proxy_url } => download_and_hash(url, *proxy_url).await,
+            ImageSource::File { path, .. } => get_image_hash(&fs::read(path)?),
+        }
+    }
+}
+
 impl ImageProcesser<'_> {
     pub fn from_message(msg: &Message) -> Result<ImageProcesser<'_>> {
         Ok(ImageProcesser::new(
@@ -52,123 +102,300 @@ impl ImageProcesser<'_> {
         ))
     }
 
-    pub async fn process(&self, include_reply: bool) -> Result<RepostSet> {
-        store_images_direct(
+    pub async fn process(
+        &self,
+        include_reply: bool,
+        settings: &ServerSettings,
+    ) -> Result<RepostSet> {
+        store_images(
             self.msg_id,
             self.server_id,
-            self.attachments,
-            self.embeds,
+            self.image_sources(settings),
             include_reply,
+            settings,
         )
         .await
     }
-}
 
-async fn store_images_direct<'a>(
-    msg_id: u64,
-    server_id: u64,
-    attachments: &'a Vec<Attachment>,
-    embeds: &'a Vec<Embed>,
-    include_reply: bool,
-) -> Result<RepostSet> {
-    let mut hashes = Vec::new();
-    if !attachments.is_empty() {
-        info!("msg {msg_id} has {} attachments", attachments.len());
-    }
-    for attachment in attachments {
-        if attachment
-            .content_type
-            .as_ref()
-            .map_or(true, |t| !t.starts_with("image"))
-        {
-            continue;
-        }
-        let download_time = Instant::now();
-        // need to actually handle download failures at some pointc
-        let bytes = attachment.download().await?;
-        warn!(
-            "msg {msg_id} has attachment with {} bytes downloaded in {:.2?}",
-            bytes.len(),
-            download_time.elapsed()
-        );
-        let parse_time = Instant::now();
-        if let Some(hash) = get_image_hash(&bytes)? {
-            warn!(
-                "msg {msg_id} has attachment with hash {} parsed in {:.2?}",
-                hash.to_base64(),
-                parse_time.elapsed()
-            );
-            hashes.push((hash, &attachment.url));
+    /// Brings the images stored for an edited message in line with its current attachments
+    /// and embeds. Only images not already stored are downloaded, and reposts that relied
+    /// on a removed image are removed with it. Returns the reposts of any images added and
+    /// whether the stored images changed at all.
+    pub async fn process_edit(
+        &self,
+        include_reply: bool,
+        settings: &ServerSettings,
+    ) -> Result<(RepostSet, bool)> {
+        let msg_id = self.msg_id;
+        let stored = read_only_db_async(move |db| db.get_message_images(msg_id)).await?;
+        let sources = self.image_sources(settings);
+
+        let (remaining, removed): (Vec<_>, Vec<_>) = stored
+            .iter()
+            .partition(|(url, _)| sources.iter().any(|source| source.url() == url));
+        let added = sources
+            .into_iter()
+            .filter(|source| !stored.iter().any(|(url, _)| source.url() == url))
+            .collect::<Vec<_>>();
+        let changed = !removed.is_empty() || !added.is_empty();
+
+        if !removed.is_empty() {
+            let removed = removed.into_iter().map(|(url, _)| url.clone()).collect();
+            let remaining = remaining
+                .into_iter()
+                .map(|(_, hash)| hash.clone())
+                .collect();
+            remove_images(msg_id, removed, remaining, settings).await?;
         }
+        let reposts = store_images(msg_id, self.server_id, added, include_reply, settings).await?;
+        Ok((reposts, changed))
     }
 
-    if !embeds.is_empty() {
-        info!("msg {msg_id} has {} embeds", embeds.len());
+    /// Finds the stored messages with images matching this message's, and how far off they
+    /// are, without storing anything. Hashes are kept in `hashed` by url, so checking the
+    /// same images against several servers only downloads them once.
+    pub async fn find_matches(
+        &self,
+        settings: &ServerSettings,
+        hashed: &mut HashMap<String, Option<String>>,
+    ) -> Result<Vec<(db::structs::Message, u32)>> {
+        let sources = self.image_sources(settings);
+        find_source_matches(sources, self.msg_id, self.server_id, settings, hashed).await
     }
-    for embed in embeds {
-        let provider_name = get_provider_name(embed);
-        if IGNORED_PROVIDERS.contains(provider_name) {
-            info!("provider {provider_name} is ignored, skipping this embed");
-            continue;
+
+    fn image_sources(&self, settings: &ServerSettings) -> Vec<ImageSource<'_>> {
+        let msg_id = self.msg_id;
+        let mut sources = Vec::new();
+        if !self.attachments.is_empty() {
+            info!("msg {msg_id} has {} attachments", self.attachments.len());
+        }
+        for attachment in self.attachments {
+            if attachment
+                .content_type
+                .as_ref()
+                .map_or(true, |t| !t.starts_with("image"))
+            {
+                continue;
+            }
+            sources.push(ImageSource::Attachment(attachment));
         }
-        info!("provider {provider_name} is not ignored, processing");
 
-        if let Some(embedi) = &embed.image {
-            info!("msg {msg_id} found image embed");
-            if let Some(hash) = download_and_hash(&embedi.url, embedi.proxy_url.as_ref()).await? {
-                hashes.push((hash, &embedi.url));
+        if !self.embeds.is_empty() {
+            info!("msg {msg_id} has {} embeds", self.embeds.len());
+        }
+        for embed in self.embeds {
+            let provider_name = get_provider_name(embed);
+            if settings.ignored_provider(provider_name) {
+                info!("provider {provider_name} is ignored, skipping this embed");
+                continue;
             }
-        } else if let Some(embedi) = &embed.thumbnail {
-            info!("msg {msg_id} found thumbnail embed");
+            info!("provider {provider_name} is not ignored, processing");
 
-            // Experimentally it seems that, with threads, all profile images are of article "link" and other images are
-            // of kind "article". This may exclude some embeds that are valid reposts, but that seems unlikely.
-            if embed
-                .kind
-                .as_ref()
-                .map(|kind| kind == "link")
-                .unwrap_or(true)
-                && provider_name == "Threads"
-            {
-                if let Some(dimension) = get_square_embed_dimension(embedi) {
-                    if dimension <= 640 {
-                        info!("Found threads thumbnail that is square with side length <= 640 ({dimension}) and of kind \"link\" this is likely a user profile image, ignoring.");
-                        continue;
+            if let Some(embedi) = &embed.image {
+                info!("msg {msg_id} found image embed");
+                sources.push(ImageSource::Embed {
+                    url: &embedi.url,
+                    proxy_url: embedi.proxy_url.as_ref(),
+                });
+            } else if let Some(embedi) = &embed.thumbnail {
+                info!("msg {msg_id} found thumbnail embed");
+
+                // Experimentally it seems that, with threads, all profile images are of article "link" and other images are
+                // of kind "article". This may exclude some embeds that are valid reposts, but that seems unlikely.
+                if embed
+                    .kind
+                    .as_ref()
+                    .map(|kind| kind == "link")
+                    .unwrap_or(true)
+                    && provider_name == "Threads"
+                {
+                    if let Some(dimension) = get_square_embed_dimension(embedi) {
+                        if dimension <= 640 {
+                            info!("Found threads thumbnail that is square with side length <= 640 ({dimension}) and of kind \"link\" this is likely a user profile image, ignoring.");
+                            continue;
+                        }
                     }
                 }
-            }
 
-            if let Some(hash) = download_and_hash(&embedi.url, embedi.proxy_url.as_ref()).await? {
-                hashes.push((hash, &embedi.url));
+                sources.push(ImageSource::Embed {
+                    url: &embedi.url,
+                    proxy_url: embedi.proxy_url.as_ref(),
+                });
             }
         }
+        sources
     }
-    let db = get_read_only_db()?;
+}
+
+async fn store_images(
+    msg_id: u64,
+    server_id: u64,
+    sources: Vec<ImageSource<'_>>,
+    include_reply: bool,
+    settings: &ServerSettings,
+) -> Result<RepostSet> {
+    let mut hashes = Vec::new();
+    for source in sources {
+        if let Some(hash) = source.hash(msg_id).await? {
+            hashes.push((hash, source.url().clone()));
+        }
+    }
+
     let mut reposts = RepostSet::new();
     for (hash, url) in hashes {
-        if include_reply {
-            let b64 = hash.to_base64();
-            let matches = db.hash_matches(&b64, server_id, msg_id)?;
-            info!(
-                "for {msg_id} with has {b64} found {} matches",
-                matches.len()
-            );
-
-            for (db_msg, db_hash_b64) in &matches {
-                if let Ok(db_hash) = ImageHash::from_base64(db_hash_b64) {
-                    let distance = hash.dist(&db_hash);
-                    info!("Hamming Distance for db_hash {db_hash_b64} is {distance}");
-                    if distance < 5 {
-                        reposts.add(*db_msg, RepostType::Image);
-                    }
-                }
+        let b64 = hash.to_base64();
+        // matches are always recorded, even when not replying, so old messages count in stats
+        let mut events = Vec::new();
+        for (db_msg, distance) in image_matches(&b64, server_id, msg_id, settings).await? {
+            events.push(RepostEvent::image(msg_id, db_msg.id, distance));
+            if include_reply {
+                reposts.add(db_msg, RepostType::Image);
             }
         }
-        writable_db_call(|mut db| db.insert_image(url, &hash.to_base64(), msg_id))?;
+        writable_db_async(move |mut db| {
+            db.insert_image(&url, &b64, msg_id)?;
+            db.add_repost_events(&events)
+        })
+        .await?;
     }
     Ok(reposts)
 }
 
+/// Stored images in the server close enough to the hash to count as a repost, along with
+/// how far off they are
+async fn image_matches(
+    b64: &str,
+    server_id: u64,
+    msg_id: u64,
+    settings: &ServerSettings,
+) -> Result<Vec<(db::structs::Message, u32)>> {
+    let query_hash = b64.to_string();
+    let matches =
+        read_only_db_async(move |db| db.hash_matches(&query_hash, server_id, msg_id)).await?;
+    info!(
+        "for {msg_id} with has {b64} found {} matches",
+        matches.len()
+    );
+
+    let mut close = Vec::new();
+    for (db_msg, db_hash_b64) in matches {
+        if let Some(distance) = hash_distance(b64, &db_hash_b64) {
+            info!("Hamming Distance for db_hash {db_hash_b64} is {distance}");
+            if distance < settings.image_distance {
+                close.push((db_msg, distance));
+            }
+        }
+    }
+    Ok(close)
+}
+
+async fn find_source_matches(
+    sources: Vec<ImageSource<'_>>,
+    msg_id: u64,
+    server_id: u64,
+    settings: &ServerSettings,
+    hashed: &mut HashMap<String, Option<String>>,
+) -> Result<Vec<(db::structs::Message, u32)>> {
+    let mut matches = Vec::new();
+    for source in sources {
+        let b64 = match hashed.get(source.url()) {
+            Some(b64) => b64.clone(),
+            None => {
+                // nothing is stored, so an image that can't be read is just left out
+                let b64 = match source.hash(msg_id).await {
+                    Ok(hash) => hash.map(|hash| hash.to_base64()),
+                    Err(why) => {
+                        warn!("failed to hash {} with error {why:?}", source.url());
+                        None
+                    }
+                };
+                hashed.insert(source.url().clone(), b64.clone());
+                b64
+            }
+        };
+        if let Some(b64) = b64 {
+            matches.extend(image_matches(&b64, server_id, msg_id, settings).await?);
+        }
+    }
+    Ok(matches)
+}
+
+/// Same as ImageProcesser::find_matches but for images linked to directly
+pub async fn find_url_matches(
+    urls: &[String],
+    msg_id: u64,
+    server_id: u64,
+    settings: &ServerSettings,
+    hashed: &mut HashMap<String, Option<String>>,
+) -> Result<Vec<(db::structs::Message, u32)>> {
+    let sources = urls
+        .iter()
+        .map(|url| ImageSource::Embed {
+            url,
+            proxy_url: None,
+        })
+        .collect();
+    find_source_matches(sources, msg_id, server_id, settings, hashed).await
+}
+
+/// Hashes and stores images that are already on disk as (discord url, path), matches are
+/// recorded but never replied to
+pub async fn store_image_files(
+    msg_id: u64,
+    server_id: u64,
+    files: Vec<(String, PathBuf)>,
+    settings: &ServerSettings,
+) -> Result<()> {
+    let sources = files
+        .into_iter()
+        .map(|(url, path)| ImageSource::File { url, path })
+        .collect();
+    store_images(msg_id, server_id, sources, false, settings).await?;
+    Ok(())
+}
+
+/// Removes images from a message, along with any image reposts to or from the message that
+/// none of the remaining images account for
+async fn remove_images(
+    msg_id: u64,
+    removed: Vec<String>,
+    remaining: Vec<String>,
+    settings: &ServerSettings,
+) -> Result<()> {
+    info!("Images {removed:?} were removed from message {msg_id}");
+    let pairs = read_only_db_async(move |db| db.get_image_reposts(msg_id)).await?;
+
+    let mut stale = Vec::new();
+    for (message, original) in pairs {
+        let other = if message == msg_id { original } else { message };
+        let other_images = read_only_db_async(move |db| db.get_message_images(other)).await?;
+        let still_matches = remaining.iter().any(|hash| {
+            other_images.iter().any(|(_, other_hash)| {
+                hash_distance(hash, other_hash).map_or(false, |d| d < settings.image_distance)
+            })
+        });
+        if !still_matches {
+            stale.push(RepostEvent::image(message, original, 0));
+        }
+    }
+
+    writable_db_async(move |mut db| {
+        db.delete_message_images(msg_id, &removed)?;
+        db.delete_repost_events(&stale)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Hamming distance between two base64 image hashes, None if either can't be parsed
+fn hash_distance(a: &str, b: &str) -> Option<u32> {
+    Some(
+        ImageHash::<Box<[u8]>>::from_base64(a)
+            .ok()?
+            .dist(&ImageHash::<Box<[u8]>>::from_base64(b).ok()?),
+    )
+}
+
 // Primarily a seperate function for testing purposes
 fn hash_img(image: &image::DynamicImage) -> ImageHash {
     HasherConfig::new()
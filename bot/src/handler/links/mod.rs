@@ -1,72 +1,74 @@
 mod filter;
 
+use super::settings::ServerSettings;
 use crate::errors::Result;
 use crate::structs::repost::{RepostSet, RepostType};
-use filter::filtered_url;
+pub(crate) use filter::filtered_url;
+use itertools::Itertools;
 
-use db::{read_only_db_call, structs::Link, writable_db_call, ReadOnlyDb, WriteableDb};
-use lazy_static::lazy_static;
+use db::structs::{Link, RepostEvent};
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
 use linkify::{LinkFinder, LinkKind};
 use log::{error, info};
-use regex::Regex;
 use serenity::model::channel::Message;
 
-fn query_link_matches(url_str: &str, server: u64) -> Result<Vec<Link>> {
-    let mut links = Vec::new();
-    for link in read_only_db_call(|db| db.query_links(url_str, server))? {
-        links.push(link)
-    }
-    Ok(links)
+async fn query_link_matches(url_str: String, server: u64, msg_id: u64) -> Result<Vec<Link>> {
+    Ok(read_only_db_async(move |db| db.query_links(&url_str, server, msg_id)).await?)
 }
 
-const IGNORED_DOMAINS: [&str; 5] = [
-    r"globle-game\.com",
-    r"discord\.com/channels",
-    r"tenor\.com/view",
-    r"heardle\.app",
-    r"worldle\.teuteuf\.fr",
-];
-
-/// returns true if the input link is one of the ignored domains
-fn ignored_domain(text: &str) -> bool {
-    lazy_static! {
-        static ref RE: Regex =
-            Regex::new(format!(r"https?://({})/?\S*", IGNORED_DOMAINS.join("|")).as_str()).unwrap();
-    }
-    RE.is_match(text)
-}
-
-fn get_links(msg: &str) -> Vec<String> {
+fn get_links(msg: &str, settings: &ServerSettings) -> Vec<String> {
     let mut finder = LinkFinder::new();
     finder.kinds(&[LinkKind::Url]);
     finder
         .links(msg)
-        .filter(|link| !ignored_domain(link.as_str()))
+        .filter(|link| !settings.ignored_domain(link.as_str()))
         .map(|x| x.as_str().to_string())
         .collect()
 }
 
-pub fn store_links_and_get_reposts(msg: &Message, include_reply: bool) -> Result<RepostSet> {
-    let mut reposts = RepostSet::new();
-    let server_id = *msg.guild_id.unwrap().as_u64();
-    for link in get_links(&msg.content) {
-        let filtered_link = match filtered_url(&link) {
-            Ok(url) => url,
+/// The links in the message content as they're stored, i.e. with tracking removed
+pub fn get_filtered_links(content: &str, settings: &ServerSettings) -> Vec<String> {
+    get_links(content, settings)
+        .into_iter()
+        .filter_map(|link| match filtered_url(&link) {
+            Ok(url) => Some(url.to_string()),
             Err(why) => {
                 error!("Failed to filter url: {why:?}");
-                continue;
+                None
             }
-        };
+        })
+        .collect()
+}
 
-        if include_reply {
-            let repost_links = query_link_matches(filtered_link.as_str(), server_id)?;
-            for rlink in repost_links {
+pub async fn store_links(
+    links: Vec<String>,
+    server_id: u64,
+    msg_id: u64,
+    include_reply: bool,
+) -> Result<RepostSet> {
+    let mut reposts = RepostSet::new();
+    for filtered_link in links {
+        // matches are always recorded, even when not replying, so old messages count in stats
+        let mut events = Vec::new();
+        let repost_links = query_link_matches(filtered_link.clone(), server_id, msg_id).await?;
+        for rlink in repost_links {
+            if rlink.message.id == msg_id {
+                continue;
+            }
+            if let Some(link_id) = rlink.id {
+                events.push(RepostEvent::link(msg_id, rlink.message.id, link_id as u64));
+            }
+            if include_reply {
                 reposts.add(rlink.message, RepostType::Link);
             }
         }
 
         // finally insert this link into db
-        writable_db_call(|mut db| db.insert_link(filtered_link.as_str(), *msg.id.as_u64()))?;
+        writable_db_async(move |mut db| {
+            db.insert_link(filtered_link.as_str(), msg_id)?;
+            db.add_repost_events(&events)
+        })
+        .await?;
     }
     // if include_reply false len should always be 0
     if reposts.len() > 0 {
@@ -75,9 +77,57 @@ pub fn store_links_and_get_reposts(msg: &Message, include_reply: bool) -> Result
     Ok(reposts)
 }
 
-pub fn get_reposts_for_message_id(message_id: u64) -> Result<RepostSet> {
+pub async fn store_links_and_get_reposts(
+    msg: &Message,
+    include_reply: bool,
+    settings: &ServerSettings,
+) -> Result<RepostSet> {
+    store_links(
+        get_filtered_links(&msg.content, settings),
+        *msg.guild_id.unwrap().as_u64(),
+        *msg.id.as_u64(),
+        include_reply,
+    )
+    .await
+}
+
+/// Brings the links stored for an edited message in line with its new content. Returns
+/// the reposts of any links added and whether the stored links changed at all.
+pub async fn update_links_for_edit(
+    content: &str,
+    server_id: u64,
+    msg_id: u64,
+    include_reply: bool,
+    settings: &ServerSettings,
+) -> Result<(RepostSet, bool)> {
+    let current = get_filtered_links(content, settings);
+    let stored = read_only_db_async(move |db| db.get_message_links(msg_id)).await?;
+
+    let removed = stored
+        .iter()
+        .filter(|link| !current.contains(link))
+        .cloned()
+        .collect_vec();
+    let added = current
+        .into_iter()
+        .filter(|link| !stored.contains(link))
+        .unique()
+        .collect_vec();
+    let changed = !removed.is_empty() || !added.is_empty();
+
+    if !removed.is_empty() {
+        info!("Links {removed:?} were edited out of message {msg_id}");
+        writable_db_async(move |mut db| db.delete_message_links(msg_id, &removed)).await?;
+    }
+    Ok((
+        store_links(added, server_id, msg_id, include_reply).await?,
+        changed,
+    ))
+}
+
+pub async fn get_reposts_for_message_id(message_id: u64) -> Result<RepostSet> {
     Ok(RepostSet::new_from_messages(
-        &read_only_db_call(|db| db.query_reposts_for_message(message_id))?,
+        &read_only_db_async(move |db| db.query_reposts_for_message(message_id)).await?,
         RepostType::Link,
     ))
 }
@@ -87,7 +137,10 @@ mod tests {
     use super::*;
     #[test]
     fn test_extract_link() {
-        let links = get_links("test msg with link https://twitter.com/user/status/idnumber?s=20");
+        let links = get_links(
+            "test msg with link https://twitter.com/user/status/idnumber?s=20",
+            &ServerSettings::default(),
+        );
 
         assert_eq!(links.len(), 1);
         assert_eq!(links[0], "https://twitter.com/user/status/idnumber?s=20");
@@ -98,6 +151,7 @@ mod tests {
         let links = get_links(
             "test msg with link https://twitter.com/user/status/idnumber?s=20 and
              another link https://www.bbc.com/news/article",
+            &ServerSettings::default(),
         );
 
         assert_eq!(links.len(), 2);
@@ -113,6 +167,7 @@ mod tests {
             and also ignore tenor https://tenor.com/view/gif-name
              another link https://www.bbc.com/news/article
              discord link but not a channel https://discord.com/developers/docs/intro",
+            &ServerSettings::default(),
         );
 
         assert_eq!(links.len(), 2);
@@ -120,14 +175,38 @@ mod tests {
         assert!(links.contains(&"https://discord.com/developers/docs/intro".to_string()));
     }
 
+    #[test]
+    fn test_filtered_links() {
+        let links = get_filtered_links(
+            "https://twitter.com/user/status/idnumber?s=20 https://www.bbc.com/news/article",
+            &ServerSettings::default(),
+        );
+
+        assert_eq!(
+            links,
+            vec![
+                "https://twitter.com/user/status/idnumber",
+                "https://www.bbc.com/news/article"
+            ]
+        );
+    }
+
     #[test]
     fn test_extract_no_link() {
         assert_eq!(
-            get_links("just a random message with no links in it").len(),
+            get_links(
+                "just a random message with no links in it",
+                &ServerSettings::default()
+            )
+            .len(),
             0
         );
         assert_eq!(
-            get_links("example@example.org isnt a link but could be by some definitions").len(),
+            get_links(
+                "example@example.org isnt a link but could be by some definitions",
+                &ServerSettings::default()
+            )
+            .len(),
             0
         );
     }
@@ -141,9 +220,12 @@ mod tests {
         
         https://globle-game.com/";
 
-        assert_eq!(get_links(message).len(), 0);
+        assert_eq!(get_links(message, &ServerSettings::default()).len(), 0);
         // Also assert with no trailing slash
-        assert_eq!(get_links("https://globle-game.com").len(), 0);
+        assert_eq!(
+            get_links("https://globle-game.com", &ServerSettings::default()).len(),
+            0
+        );
     }
 
     #[test]
@@ -154,9 +236,12 @@ mod tests {
         
         https://heardle.app/";
 
-        assert_eq!(get_links(message).len(), 0);
+        assert_eq!(get_links(message, &ServerSettings::default()).len(), 0);
         // Also assert with no trailing slash
-        assert_eq!(get_links("https://heardle.app").len(), 0);
+        assert_eq!(
+            get_links("https://heardle.app", &ServerSettings::default()).len(),
+            0
+        );
     }
 
     #[test]
@@ -167,8 +252,11 @@ mod tests {
         🟩🟩🟩🟩🟩🎉
         https://worldle.teuteuf.fr/";
 
-        assert_eq!(get_links(message).len(), 0);
+        assert_eq!(get_links(message, &ServerSettings::default()).len(), 0);
         // Also assert with no trailing slash
-        assert_eq!(get_links("https://worldle.teuteuf.fr").len(), 0);
+        assert_eq!(
+            get_links("https://worldle.teuteuf.fr", &ServerSettings::default()).len(),
+            0
+        );
     }
 }
@@ -1,37 +1,52 @@
+mod backfill;
 mod commands;
-mod images;
-mod links;
+mod dms;
+mod feedback;
+pub(crate) mod images;
+pub(crate) mod links;
+mod replies;
+pub(crate) mod settings;
+mod threads;
 
 use crate::errors::{Error, Result};
 use crate::structs::reply::Reply;
 use crate::structs::repost::RepostSet;
 
-use db::{get_read_only_db, get_writeable_db, writable_db_call, ReadOnlyDb, WriteableDb};
+use db::structs::ChannelMode;
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
 use images::ImageProcesser;
 use log::{debug, error, info, trace, warn};
-use rand::seq::SliceRandom;
-use rand::{random, thread_rng};
+use settings::ServerSettings;
 
 use serenity::{
     async_trait,
     cache::Cache,
     model::{
-        channel::{Channel, ChannelType, GuildChannel, Message, MessageType},
+        application::{command::Command, interaction::Interaction},
+        channel::{
+            Channel, ChannelType, GuildChannel, Message, MessageType, PartialGuildChannel, Reaction,
+        },
         gateway::Ready,
-        guild::Member,
+        guild::{Guild, Member, UnavailableGuild},
         id::{ChannelId, GuildId, MessageId},
         permissions::Permissions,
-        prelude::MessageUpdateEvent,
+        prelude::{MessageUpdateEvent, ThreadListSyncEvent},
     },
     prelude::*,
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::time::{Duration, Instant};
 
+pub(crate) use commands::parse_filter;
+
 pub struct Handler;
 
+// how long after being removed from a server before everything stored for it is erased,
+// so being kicked by accident doesn't lose the server's history
+const ERASE_GRACE_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
 #[inline(always)]
-pub fn log_error<T>(r: rusqlite::Result<T>, label: &str) {
+pub fn log_error<T>(r: db::Result<T>, label: &str) {
     match r {
         Ok(_) => (),
         Err(why) => error!("{label} failed with error: {why:?}"),
@@ -64,30 +79,43 @@ async fn process_discord_message(ctx: &Context, msg: &Message) -> Result<db::str
     }
     let now = Instant::now();
 
-    let db = get_writeable_db()?;
-
     let author_id = *msg.author.id.as_u64();
-    db.add_user(
-        author_id,
-        &msg.author.name,
-        msg.author.bot,
-        msg.author.discriminator,
-    )?;
+    if read_only_db_async(move |db| db.is_opted_out(author_id)).await? {
+        return Err(Error::OptedOut);
+    }
 
     let server = msg
         .guild_id
         .ok_or(Error::ConstStr("Guild id doesn't exist on message"))?;
     let server_id = *server.as_u64();
-    let server_name = &server.name(ctx);
-    db.update_server(server_id, server_name)?;
-
-    // get channel id and load message
+    let server_name = server.name(ctx);
     let channel_id = *msg.channel_id.as_u64();
-    let channel_name = msg.channel_id.name(&ctx.cache).await;
-    // we can assume channel is visible if we are receiving messages for it
-    db.update_channel(channel_id, server_id, &channel_name.unwrap(), true)?;
+    // threads aren't cached as channels, so a missing name means the message is in one
+    let (channel_name, parent) = match msg.channel_id.name(&ctx.cache).await {
+        Some(name) => (name, None),
+        None => {
+            let thread = threads::get_thread(ctx, server, msg.channel_id).await?;
+            (thread.name, thread.parent_id.map(|id| *id.as_u64()))
+        }
+    };
 
-    let ret = db.add_message(msg.id, channel_id, server_id, author_id)?;
+    let msg_id = msg.id;
+    let (author_name, author_bot, author_discriminator) = (
+        msg.author.name.clone(),
+        msg.author.bot,
+        msg.author.discriminator,
+    );
+    let ret = writable_db_async(move |db| {
+        db.add_user(author_id, &author_name, author_bot, author_discriminator)?;
+        db.update_server(server_id, &server_name)?;
+        // we can assume channel is visible if we are receiving messages for it
+        match parent {
+            Some(parent) => db.update_thread(channel_id, server_id, &channel_name, parent, true)?,
+            None => db.update_channel(channel_id, server_id, &channel_name, true)?,
+        }
+        db.add_message(msg_id, channel_id, server_id, author_id)
+    })
+    .await?;
 
     trace!(
         "process_discord_message time elapsed: {:.2?}",
@@ -97,10 +125,42 @@ async fn process_discord_message(ctx: &Context, msg: &Message) -> Result<db::str
     Ok(ret)
 }
 
+/// Brings the images stored for an edited message in line with its attachments and embeds
+async fn update_images_for_edit(
+    ctx: &Context,
+    new: &Option<Message>,
+    event: &MessageUpdateEvent,
+    server_id: u64,
+    should_reply: bool,
+    settings: &ServerSettings,
+) -> Result<(RepostSet, bool)> {
+    // partial updates, like embeds loading, leave out whatever didn't change so the
+    // full message is needed to know which images it still has
+    let fetched;
+    let full_msg = match new {
+        Some(msg) => msg,
+        None => {
+            fetched = event.channel_id.message(ctx, event.id).await?;
+            &fetched
+        }
+    };
+    let embeds = event.embeds.as_ref().unwrap_or(&full_msg.embeds);
+    let attachments = event.attachments.as_ref().unwrap_or(&full_msg.attachments);
+    let content = event.content.as_ref().unwrap_or(&full_msg.content);
+
+    // commands aren't checked, e.g. the embed for a link looked up with !rpm original
+    if commands::has_command_prefix(content) {
+        return Ok((RepostSet::new(), false));
+    }
+    ImageProcesser::new(*event.id.as_u64(), server_id, attachments, embeds)
+        .process_edit(should_reply, settings)
+        .await
+}
+
 async fn process_message_update<'a>(
-    _ctx: &Context,
+    ctx: &Context,
     _old_if_available: &Option<Message>,
-    _new: &Option<Message>,
+    new: &Option<Message>,
     event: &'a MessageUpdateEvent,
 ) -> Result<Option<Reply<'a>>> {
     let msg_id = *event.id.as_u64();
@@ -108,50 +168,76 @@ async fn process_message_update<'a>(
         warn!("Received message update on msg_id {msg_id} with no guild_id, can't process");
         return Ok(None);
     }
-    let db_msg_maybe = get_read_only_db()?.get_message(event.id)?;
-    if db_msg_maybe.is_none() {
-        warn!("Received message update on msg_id {msg_id} but haven't already processed message, can't process");
+    let event_id = event.id;
+    let db_msg = match read_only_db_async(move |db| db.get_message(event_id)).await? {
+        Some(db_msg) => db_msg,
+        None => {
+            warn!("Received message update on msg_id {msg_id} but haven't already processed message, can't process");
+            return Ok(None);
+        }
+    };
+    let channel_id = db_msg.channel;
+    let mode = read_only_db_async(move |db| db.get_channel_mode(channel_id)).await?;
+    if mode == ChannelMode::Off {
         return Ok(None);
     }
-    // TODO: no more unwraps
-    let db_msg = db_msg_maybe.unwrap();
-    // just handling embeds right now as it's a common occurance that the embed
-    // only gets loaded after the message is first sent. As such, if we don't
-    // handle it, embeds will get routinely missed.
-    //
-    // We should eventually use this to check if existing images / links are
-    // removed and if attachments / links are added.
+    let settings = ServerSettings::load(db_msg.server).await?;
+    // we should reply if the message is recent, if it's an older message
+    // being updated we'll leave it be
+    let should_reply = db_msg.is_recent(settings.recent_window) && mode != ChannelMode::Silent;
+
+    // replies that use this message as the original have to be found before anything
+    // is removed, as they're found through the reposts being removed
+    let citing = read_only_db_async(move |db| db.get_replies_citing(msg_id)).await?;
+    let mut reposts = RepostSet::new();
+    // set when something stored for the message was removed or added, so replies to or
+    // citing the message might be out of date
+    let mut changed = false;
+
+    // embeds are a common occurance here as they often only get loaded after the message
+    // is first sent. As such, if we don't handle it, embeds will get routinely missed.
     if event.embeds.is_some() || event.attachments.is_some() {
-        // we should reply if the message is recent, if it's an older message
-        // being updated we'll leave it be
-        let should_reply = db_msg.is_recent();
-
-        let embeds_default = vec![];
-        let attachments_default = vec![];
-
-        let embeds = event.embeds.as_ref().map_or(&embeds_default, |r| r);
-        let attachments = event
-            .attachments
-            .as_ref()
-            .map_or(&attachments_default, |r| r);
-
-        let mut reposts = ImageProcesser::new(
-            msg_id,
-            *event.guild_id.unwrap().as_u64(),
-            attachments,
-            embeds,
-        )
-        .process(should_reply)
-        .await?;
-        if should_reply && reposts.len() > 0 {
-            // need to get any link reposts if we're gonna edit the reply
-            reposts.union(&links::get_reposts_for_message_id(msg_id)?);
-            return Ok(reposts.generate_reply_for_message_id(
-                &event.id,
-                &event.channel_id,
-                db_msg.created_at,
-            ));
+        let (image_reposts, images_changed) =
+            update_images_for_edit(ctx, new, event, db_msg.server, should_reply, &settings).await?;
+        reposts.union(&image_reposts);
+        changed |= images_changed;
+    }
+
+    if let Some(content) = &event.content {
+        if !commands::has_command_prefix(content) {
+            let (link_reposts, links_changed) = links::update_links_for_edit(
+                content,
+                db_msg.server,
+                msg_id,
+                should_reply,
+                &settings,
+            )
+            .await?;
+            reposts.union(&link_reposts);
+            changed |= links_changed;
+        }
+    }
+
+    if changed {
+        for reply in citing {
+            replies::rerender_reply(ctx, &reply).await?;
         }
+        if let Some(reply) = read_only_db_async(move |db| db.get_reply(msg_id)).await? {
+            // everything found is already stored so the reply can be rebuilt from the db
+            replies::rerender_reply(ctx, &reply).await?;
+            return Ok(None);
+        }
+    }
+
+    if should_reply && reposts.len() > 0 {
+        // need to get any link reposts if we're gonna edit the reply
+        reposts.union(&links::get_reposts_for_message_id(msg_id).await?);
+        return Ok(reposts.generate_reply_for_message_id(
+            &event.id,
+            &event.channel_id,
+            db_msg.created_at,
+            mode,
+        ));
     }
 
     Ok(None)
@@ -163,7 +249,16 @@ async fn process_message<'a>(
     new: bool,
 ) -> Result<Option<Reply<'a>>> {
     // need to do this first, also does validation
-    let db_msg = process_discord_message(ctx, msg).await?;
+    let db_msg = match process_discord_message(ctx, msg).await {
+        // nothing is stored for opted out users but they can still run commands, i.e. optin
+        Err(Error::OptedOut) if new && commands::has_command_prefix(&msg.content) => {
+            return Ok(commands::handle_command(ctx, msg).await);
+        }
+        ret => ret?,
+    };
+
+    let channel_id = db_msg.channel;
+    let mode = read_only_db_async(move |db| db.get_channel_mode(channel_id)).await?;
 
     let ret = if commands::has_command_prefix(&msg.content) {
         if new {
@@ -171,20 +266,29 @@ async fn process_message<'a>(
         } else {
             None
         }
+    } else if mode == ChannelMode::Off {
+        trace!("skipping message {} in channel with mode off", msg.id);
+        None
     } else {
+        let settings = ServerSettings::load(db_msg.server).await?;
         let mut repost_set = RepostSet::new();
         if !db_msg.is_embed_parsed() {
-            repost_set.union(&ImageProcesser::from_message(msg)?.process(new).await?);
+            repost_set.union(
+                &ImageProcesser::from_message(msg)?
+                    .process(new, &settings)
+                    .await?,
+            );
         };
 
         if !db_msg.is_repost_parsed() {
-            repost_set.union(&links::store_links_and_get_reposts(msg, new)?);
+            repost_set.union(&links::store_links_and_get_reposts(msg, new, &settings).await?);
         };
 
-        repost_set.generate_reply_for_message(msg)
+        repost_set.generate_reply_for_message(msg, mode)
     };
 
-    get_writeable_db()?.mark_message_all_checked(msg.id)?;
+    let msg_id = msg.id;
+    writable_db_async(move |db| db.mark_message_all_checked(msg_id)).await?;
 
     Ok(ret)
 }
@@ -196,91 +300,38 @@ async fn process_discord_message_slow(ctx: &Context, msg: &Message) -> Result<()
         .ok_or_else(|| Error::ConstStr("Guild id doesn't exist"))?;
 
     if let Some(nickname) = msg.author.nick_in(ctx, server_id).await {
-        let db = get_writeable_db()?;
-        db.add_nickname(*msg.author.id.as_u64(), *server_id.as_u64(), &nickname)?;
+        let author_id = *msg.author.id.as_u64();
+        writable_db_async(move |db| db.add_nickname(author_id, *server_id.as_u64(), &nickname))
+            .await?;
     }
 
     Ok(())
 }
 
-async fn process_old_messages(ctx: &Context, server_id: &u64) -> Result<usize> {
-    const LIMIT: u64 = 50;
-    let db = get_read_only_db()?;
-    let (channel_id, query, base_msg) = match db.get_newest_unchecked_message(*server_id)? {
-        Some(msg) => (
-            msg.channel,
-            format!("?limit={LIMIT}&around={}", msg.id),
-            Some(msg.id),
-        ),
-        None => {
-            // if there is nothing to query we really don't need to spam the api all the time
-            if random::<f64>() > 0.015 {
-                return Ok(0);
-            }
-            let channels = db.get_known_channels(*server_id)?;
-            let mut rng = thread_rng();
-            let channel = channels
-                .choose(&mut rng)
-                .ok_or(Error::ConstStr("Rng choose failed for some reason"))?;
-
-            (channel.id, format!("?limit={LIMIT}"), None)
-        }
-    };
-
-    let messages = ctx.http.get_messages(channel_id, &query).await?;
-    let db = get_writeable_db()?;
-    if !messages.is_empty() {
-        let len = messages.len();
-        info!("received {len} messages for channel id: {channel_id} and query_string {query}");
-        let mut ids = HashSet::with_capacity(len);
-        for mut msg in messages {
-            let id = *msg.id.as_u64();
-            ids.insert(id);
-
-            if msg.author.bot || !regular_text_msg(msg.kind) {
-                if let Some(base_id) = base_msg {
-                    if base_id == id {
-                        warn!("base msg id {id} either a bot or not a regular text message");
-                        db.soft_delete_message(base_id)?;
-                    }
-                }
-            } else {
-                let db_msg_maybe = db.get_message(msg.id)?;
-                if msg.guild_id.is_none() {
-                    msg.guild_id = Some(GuildId(*server_id));
-                }
-                if let Err(why) = process_message(ctx, &msg, false).await {
-                    warn!("Failed to process old message {} with error {why:?}", id);
-                }
-
-                if let Some(db_msg) = db_msg_maybe {
-                    if !db_msg.is_deleted() && !db_msg.is_checked_old() {
-                        // mark as checked old if we had this in the db before processing just now
-                        db.mark_message_checked_old(msg.id)?;
-                    }
-                } else {
-                    debug!(
-                        "message {} not already in db, must have been sent whilst server down",
-                        msg.id
-                    );
-                }
-            }
-        }
+/// Erases everything stored for servers the bot left longer ago than the grace period
+async fn erase_left_servers() -> Result<()> {
+    let servers = read_only_db_async(|db| db.get_servers_left_for(ERASE_GRACE_PERIOD)).await?;
+    for server_id in servers {
+        info!("erasing data for server {server_id} as it was left over {ERASE_GRACE_PERIOD:?} ago");
+        writable_db_async(move |mut db| db.erase_server(server_id)).await?;
+    }
+    Ok(())
+}
 
-        // if when querying around ID the ID itself doesn't show up,
-        // this would seem to indicate it was deleted and we missed it.
-        // As this is unclear, we just soft delete it.
-        if let Some(base_msg) = base_msg {
-            if !ids.contains(&base_msg) {
-                warn!("did not received base msg id {base_msg} when querying for messages");
-                db.soft_delete_message(base_msg)?;
+/// DMs aren't stored, links and images in them are just checked against the servers the
+/// sender shares with the bot
+async fn process_direct_message(ctx: &Context, msg: &Message) {
+    if msg.author.bot || !regular_text_msg(msg.kind) {
+        return;
+    }
+    match dms::check_direct_message(ctx, msg).await {
+        Ok(Some(reply)) => {
+            if let Err(why) = reply.send(ctx).await {
+                error!("message: failed to reply to DM {why:?}");
             }
         }
-
-        Ok(len)
-    } else {
-        debug!("received no messages to process");
-        Ok(0)
+        Ok(None) => (),
+        Err(why) => error!("message: failed to check DM {}: {why:?}", msg.id),
     }
 }
 
@@ -293,6 +344,10 @@ impl Handler {
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
+        if msg.guild_id.is_none() {
+            process_direct_message(&ctx, &msg).await;
+            return;
+        }
         match process_message(&ctx, &msg, true).await {
             Ok(result) => {
                 if let Some(reply) = result {
@@ -307,6 +362,7 @@ impl EventHandler for Handler {
             }
             Err(why) => match why {
                 Error::BotMessage => debug!("Skipped processing bot message"),
+                Error::OptedOut => debug!("Skipped processing message from opted out user"),
                 _ => error!("message: failed to process messsage: {why:?}"),
             },
         }
@@ -334,43 +390,53 @@ impl EventHandler for Handler {
 
     async fn message_delete(
         &self,
-        _ctx: Context,
+        ctx: Context,
         _channel_id: ChannelId,
         message_id: MessageId,
         _guild_id: Option<GuildId>,
     ) {
-        let db = match get_writeable_db() {
-            Ok(db) => db,
-            Err(why) => {
-                error!("Error getting db: {why:?}");
-                return;
-            }
-        };
-
-        match db.delete_message(message_id) {
-            Ok(_) => info!(
-                "successfully deleted message id {} from db",
-                *message_id.as_u64()
-            ),
-            Err(why) => error!(
+        if let Err(why) = replies::message_deleted(&ctx, message_id).await {
+            error!(
                 "failed to delete message id {} with following error {:?}",
                 message_id.as_u64(),
                 why
-            ),
-        };
+            );
+        }
+    }
+
+    async fn message_delete_bulk(
+        &self,
+        ctx: Context,
+        _channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+        _guild_id: Option<GuildId>,
+    ) {
+        for message_id in message_ids {
+            if let Err(why) = replies::message_deleted(&ctx, message_id).await {
+                error!(
+                    "failed to delete message id {} with following error {:?}",
+                    message_id.as_u64(),
+                    why
+                );
+            }
+        }
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        if let Err(why) = feedback::check_not_repost_reaction(&ctx, &reaction).await {
+            error!("reaction_add: failed to check reaction with error: {why:?}");
+        }
     }
 
     async fn channel_create(&self, ctx: Context, channel: &GuildChannel) {
         let visible = bot_read_channel_permission(&ctx, channel).await;
+        let (id, server, name) = (
+            *channel.id.as_u64(),
+            *channel.guild_id.as_u64(),
+            channel.name.clone(),
+        );
         log_error(
-            writable_db_call(|db| {
-                db.update_channel(
-                    *channel.id.as_u64(),
-                    *channel.guild_id.as_u64(),
-                    &channel.name,
-                    visible,
-                )
-            }),
+            writable_db_async(move |db| db.update_channel(id, server, &name, visible)).await,
             "Db update channel",
         );
     }
@@ -382,7 +448,7 @@ impl EventHandler for Handler {
                 let (id, name, server) = (channel.id, channel.name, *channel.guild_id.as_u64());
                 info!("received channel update for channel id {id} with name {name} in server {server}, visibility is now: {visible}");
                 log_error(
-                    writable_db_call(|db| db.update_channel_visibility(channel.id, visible)),
+                    writable_db_async(move |db| db.update_channel_visibility(id, visible)).await,
                     "Updating visibility",
                 );
             }
@@ -394,86 +460,155 @@ impl EventHandler for Handler {
 
     async fn channel_delete(&self, _ctx: Context, channel: &GuildChannel) {
         trace!("recieved channel delete for {channel:?}");
+        let id = channel.id;
         log_error(
-            writable_db_call(|db| db.delete_channel(channel.id)),
+            writable_db_async(move |db| db.delete_channel(id)).await,
             "Db delete channel",
         );
     }
 
+    async fn thread_create(&self, ctx: Context, thread: GuildChannel) {
+        if let Err(why) = threads::store_thread(&ctx, &thread).await {
+            error!("thread_create: failed to store thread with error: {why:?}");
+        }
+    }
+
+    async fn thread_update(&self, ctx: Context, thread: GuildChannel) {
+        if let Err(why) = threads::store_thread(&ctx, &thread).await {
+            error!("thread_update: failed to store thread with error: {why:?}");
+        }
+    }
+
+    async fn thread_delete(&self, _ctx: Context, thread: PartialGuildChannel) {
+        trace!("recieved thread delete for {thread:?}");
+        let id = thread.id;
+        log_error(
+            writable_db_async(move |db| db.delete_channel(id)).await,
+            "Db delete thread",
+        );
+    }
+
+    async fn thread_list_sync(&self, ctx: Context, thread_list_sync: ThreadListSyncEvent) {
+        for thread in thread_list_sync.threads {
+            if let Err(why) = threads::store_thread(&ctx, &thread).await {
+                error!("thread_list_sync: failed to store thread with error: {why:?}");
+            }
+        }
+    }
+
     async fn guild_member_update(
         &self,
         _ctx: Context,
         _old_if_available: Option<Member>,
         new: Member,
     ) {
-        let db = match get_writeable_db() {
-            Ok(db) => db,
-            Err(why) => {
-                error!("Error getting db: {why:?}");
-                return;
-            }
-        };
         let author_id = *new.user.id.as_u64();
-        if let Err(why) = db.add_user(
-            author_id,
-            &new.user.name,
-            new.user.bot,
-            new.user.discriminator,
-        ) {
-            error!("Error adding user: {why:?}");
+        let server_id = *new.guild_id.as_u64();
+        let result = writable_db_async(move |db| {
+            if let Err(why) = db.add_user(
+                author_id,
+                &new.user.name,
+                new.user.bot,
+                new.user.discriminator,
+            ) {
+                error!("Error adding user: {why:?}");
+                return Ok(());
+            }
+
+            if let Some(nickname) = new.nick {
+                // the server may not be stored yet if nothing has been posted since joining
+                if let Err(why) = db
+                    .update_server(server_id, &None)
+                    .and_then(|_| db.add_nickname(author_id, server_id, &nickname))
+                {
+                    error!("Error adding nickname: {why:?}");
+                }
+            }
+            Ok(())
+        })
+        .await;
+        log_error(result, "Db guild member update");
+    }
+
+    async fn guild_create(&self, _ctx: Context, guild: Guild, _is_new: bool) {
+        // stored straight away so settings can be changed before anything is posted, and we
+        // may have been added back during the grace period
+        let (server_id, name) = (*guild.id.as_u64(), Some(guild.name));
+        log_error(
+            writable_db_async(move |db| {
+                db.update_server(server_id, &name)?;
+                db.mark_server_joined(server_id)
+            })
+            .await,
+            "Db mark server joined",
+        );
+    }
+
+    async fn guild_delete(
+        &self,
+        _ctx: Context,
+        incomplete: UnavailableGuild,
+        _full: Option<Guild>,
+    ) {
+        // unavailable means an outage, otherwise we were kicked or the server was deleted
+        if incomplete.unavailable {
+            warn!("server {} became unavailable", incomplete.id);
             return;
         }
 
-        if let Some(nickname) = new.nick {
-            if let Err(why) = db.add_nickname(author_id, *new.guild_id.as_u64(), &nickname) {
-                error!("Error adding nickname: {why:?}");
-                return;
+        info!(
+            "removed from server {}, erasing its data in {ERASE_GRACE_PERIOD:?}",
+            incomplete.id
+        );
+        let server_id = *incomplete.id.as_u64();
+        log_error(
+            writable_db_async(move |db| db.mark_server_left(server_id)).await,
+            "Db mark server left",
+        );
+        tokio::spawn(async move {
+            tokio::time::sleep(ERASE_GRACE_PERIOD).await;
+            if let Err(why) = erase_left_servers().await {
+                error!("guild_delete: failed to erase left servers with error: {why:?}");
             }
-        }
+        });
     }
 
-    async fn ready(&self, _: Context, ready: Ready) {
+    async fn ready(&self, ctx: Context, ready: Ready) {
         info!("{} is connected!", ready.user.name);
+        backfill::mark_connected();
+
+        if let Err(why) =
+            Command::set_global_application_commands(&ctx.http, commands::register_commands).await
+        {
+            error!("ready: failed to register slash commands with error: {why:?}");
+        }
     }
 
-    async fn cache_ready(&self, ctx: Context, guilds: Vec<GuildId>) {
-        let db = match get_writeable_db() {
-            Ok(db) => db,
-            Err(why) => {
-                error!("Error getting db: {why:?}");
-                return;
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::ApplicationCommand(command) = interaction {
+            if let Err(why) = commands::handle_interaction(&ctx, &command).await {
+                error!("interaction_create: failed to handle command with error: {why:?}");
             }
-        };
+        }
+    }
 
+    async fn cache_ready(&self, ctx: Context, guilds: Vec<GuildId>) {
         let ctx: &'static Context = Box::leak(Box::new(ctx));
+        // catch up on any servers whose grace period ran out whilst we were down
+        if let Err(why) = erase_left_servers().await {
+            error!("cache_ready: failed to erase left servers with error: {why:?}");
+        }
+
         for guild in guilds {
             let server_name = guild.name(ctx);
+            let g = *guild.as_u64();
 
             log_error(
-                db.update_server(*guild.as_u64(), &server_name),
+                writable_db_async(move |db| db.update_server(g, &server_name)).await,
                 "Update server name from cache_ready",
             );
 
-            let g = *guild.as_u64();
-            tokio::spawn(async move {
-                loop {
-                    let tts = match process_old_messages(ctx, &g).await {
-                        Ok(val) => {
-                            if val == 0 {
-                                10 * 60
-                            } else {
-                                45
-                            }
-                        }
-                        Err(why) => {
-                            warn!("process old messages with err {why:?}");
-                            240
-                        }
-                    };
-                    trace!("process old message task sleeping {tts}s");
-                    tokio::time::sleep(Duration::from_secs(tts)).await;
-                }
-            });
+            tokio::spawn(backfill::run(ctx, g));
 
             match guild.channels(ctx).await {
                 Ok(all_channels) => {
@@ -492,60 +627,42 @@ impl EventHandler for Handler {
                         .collect::<Vec<String>>();
 
                     info!("found server with id {guild} and channels {channel_list:?}");
-                    let channels_stored = match db.get_channel_list(guild) {
-                        Ok(cs) => HashMap::from_iter(cs),
-                        Err(_why) => HashMap::new(),
-                    };
+                    let channels_stored =
+                        match read_only_db_async(move |db| db.get_channel_list(guild)).await {
+                            Ok(cs) => HashMap::from_iter(cs),
+                            Err(_why) => HashMap::new(),
+                        };
                     for (id, name) in channels_stored.clone() {
                         if !channels.contains_key(&id) {
                             warn!("stored channel {name} with id {id} no longer exists on server, deleting");
-                            log_error(db.delete_channel(id), "Db delete channel");
+                            log_error(
+                                writable_db_async(move |db| db.delete_channel(id)).await,
+                                "Db delete channel",
+                            );
                         }
                     }
 
-                    // insert all channels to update names and build visibility map
-                    let mut visibility_map = HashMap::with_capacity(channels.len());
-                    for (id, channel) in channels.clone() {
+                    // insert all channels to update names and visibility
+                    for channel in channels.values().cloned() {
                         let visible = bot_read_channel_permission(ctx, &channel).await;
-                        visibility_map.insert(id, visible);
                         log_error(
-                            db.update_channel(
-                                *channel.id.as_u64(),
-                                *channel.guild_id.as_u64(),
-                                &channel.name,
-                                visible,
-                            ),
+                            writable_db_async(move |db| {
+                                db.update_channel(
+                                    *channel.id.as_u64(),
+                                    *channel.guild_id.as_u64(),
+                                    &channel.name,
+                                    visible,
+                                )
+                            })
+                            .await,
                             "Db update channel",
                         );
                     }
 
-                    // check for most recent message
-                    for id in channels
-                        .keys()
-                        .filter(|id| *visibility_map.get(id).unwrap_or(&true))
-                        .map(|id| *id.as_u64())
-                    {
-                        match ctx.http.get_messages(id, "?limit=1").await {
-                            Ok(mut msg_vec) => {
-                                if let Some(msg) = msg_vec.pop() {
-                                    if !msg.author.bot {
-                                        log_error(
-                                            db.add_message(
-                                                msg.id,
-                                                *msg.channel_id.as_u64(),
-                                                *guild.as_u64(),
-                                                *msg.author.id.as_u64(),
-                                            ),
-                                            "db add message",
-                                        );
-                                    }
-                                }
-                            }
-                            Err(why) => {
-                                warn!("failed to load most recent message for id {id} {why:?}")
-                            }
-                        }
+                    if let Err(why) = threads::load_threads(ctx, guild, channels.values()).await {
+                        warn!("failed to load threads for guild {guild} {why:?}");
                     }
+                    tokio::spawn(backfill::catch_up(ctx, g));
                 }
                 Err(why) => error!(
                     "failed to load channels for guild {} with error {why:?}",
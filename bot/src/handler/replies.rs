@@ -0,0 +1,78 @@
+use crate::errors::Result;
+use crate::structs::repost::RepostSet;
+
+use db::structs::{ChannelMode, Reply};
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
+use log::{info, warn};
+use serenity::{
+    model::id::{ChannelId, MessageId},
+    prelude::*,
+};
+
+/// Deletes our reply from discord and the db
+pub async fn delete_reply(ctx: &Context, reply: &Reply) -> Result<()> {
+    info!("Deleting reply w/ id {}", reply.id);
+    let reply_id = reply.id;
+    writable_db_async(move |db| db.delete_reply(reply_id)).await?;
+    if let Err(why) = ctx.http.delete_message(reply.channel, reply.id).await {
+        // most likely someone else already deleted it
+        warn!("Failed to delete reply {} with error {why:?}", reply.id);
+    }
+    Ok(())
+}
+
+/// Rebuilds our reply to a message from the reposts stored for it, editing the reply if
+/// there are still reposts to show or deleting it otherwise
+pub async fn rerender_reply(ctx: &Context, reply: &Reply) -> Result<()> {
+    let message_id = reply.replied_to;
+    let msg = match read_only_db_async(move |db| db.get_message(MessageId(message_id))).await? {
+        Some(msg) => msg,
+        None => return delete_reply(ctx, reply).await,
+    };
+    let originals = read_only_db_async(move |db| db.get_repost_originals(message_id)).await?;
+
+    let (msg_id, channel_id) = (MessageId(msg.id), ChannelId(msg.channel));
+    // an existing reply is always edited, regardless of the channel's current mode
+    match RepostSet::from_originals(&originals).generate_reply_for_message_id(
+        &msg_id,
+        &channel_id,
+        msg.created_at,
+        ChannelMode::Reply,
+    ) {
+        Some(new_reply) => new_reply.send(ctx).await,
+        None => delete_reply(ctx, reply).await,
+    }
+}
+
+/// Cleans up after a message is deleted. Our reply to it is deleted, and any replies that
+/// used it as the original are rebuilt with the remaining originals, or deleted if there
+/// aren't any left.
+pub async fn message_deleted(ctx: &Context, message_id: MessageId) -> Result<()> {
+    let id = *message_id.as_u64();
+    let (own_reply, citing, deleted_reply) = read_only_db_async(move |db| {
+        Ok((
+            db.get_reply(id)?,
+            db.get_replies_citing(id)?,
+            db.get_reply_by_id(id)?,
+        ))
+    })
+    .await?;
+
+    writable_db_async(move |mut db| {
+        if deleted_reply.is_some() {
+            // one of our replies was deleted by someone else
+            db.delete_reply(id)?;
+        }
+        db.delete_message(message_id)
+    })
+    .await?;
+    info!("successfully deleted message id {id} from db");
+
+    if let Some(reply) = own_reply {
+        delete_reply(ctx, &reply).await?;
+    }
+    for reply in citing {
+        rerender_reply(ctx, &reply).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,305 @@
+use crate::errors::{Error, Result};
+
+use db::{read_only_db_async, ReadOnlyDb};
+use itertools::Itertools;
+use log::warn;
+use regex::Regex;
+use serenity::model::channel::ReactionType;
+use std::collections::HashSet;
+
+const DEFAULT_IGNORED_DOMAINS: [&str; 5] = [
+    "globle-game.com",
+    "discord.com/channels",
+    "tenor.com/view",
+    "heardle.app",
+    "worldle.teuteuf.fr",
+];
+
+const DEFAULT_IGNORED_PROVIDERS: [&str; 3] = ["Tenor", "YouTube", "Apple Music"];
+
+// Hamming distance between two image hashes below which they're considered the same image
+const DEFAULT_IMAGE_DISTANCE: u32 = 5;
+
+// seconds after a message is sent where an edit can still trigger a repost reply
+const DEFAULT_RECENT_WINDOW: i64 = 15;
+
+const DEFAULT_NOT_REPOST_EMOJI: &str = "❌";
+
+// how many entries the reposts and reposters leaderboards go down to, across every page
+const DEFAULT_LEADERBOARD_SIZE: u64 = 50;
+const MAX_LEADERBOARD_SIZE: u64 = 1000;
+
+/// Settings that can be changed per server with `!rpm config`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SettingKey {
+    IgnoredDomains,
+    IgnoredProviders,
+    ImageDistance,
+    RecentWindow,
+    NotRepostEmoji,
+    LeaderboardSize,
+}
+
+impl SettingKey {
+    pub const ALL: [SettingKey; 6] = [
+        SettingKey::IgnoredDomains,
+        SettingKey::IgnoredProviders,
+        SettingKey::ImageDistance,
+        SettingKey::RecentWindow,
+        SettingKey::NotRepostEmoji,
+        SettingKey::LeaderboardSize,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            SettingKey::IgnoredDomains => "ignored_domains",
+            SettingKey::IgnoredProviders => "ignored_providers",
+            SettingKey::ImageDistance => "image_distance",
+            SettingKey::RecentWindow => "recent_window",
+            SettingKey::NotRepostEmoji => "not_repost_emoji",
+            SettingKey::LeaderboardSize => "leaderboard_size",
+        }
+    }
+
+    pub const fn description(&self) -> &'static str {
+        match self {
+            SettingKey::IgnoredDomains => "comma separated domains (and paths) to never check",
+            SettingKey::IgnoredProviders => "comma separated embed providers to skip images for",
+            SettingKey::ImageDistance => "how different two images can be and still be a repost",
+            SettingKey::RecentWindow => "seconds after posting an edit can still get a reply",
+            SettingKey::NotRepostEmoji => "reaction on a reply that marks it as not a repost",
+            SettingKey::LeaderboardSize => "how many entries reposts and reposters list",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SettingKey> {
+        SettingKey::ALL
+            .into_iter()
+            .find(|key| key.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerSettings {
+    ignored_domains: Vec<String>,
+    ignored_domains_re: Regex,
+    ignored_providers: HashSet<String>,
+    pub image_distance: u32,
+    pub recent_window: i64,
+    not_repost_emoji: String,
+    pub leaderboard_size: u64,
+}
+
+impl Default for ServerSettings {
+    fn default() -> ServerSettings {
+        let ignored_domains = DEFAULT_IGNORED_DOMAINS.map(String::from).to_vec();
+        ServerSettings {
+            ignored_domains_re: domains_regex(&ignored_domains),
+            ignored_domains,
+            ignored_providers: DEFAULT_IGNORED_PROVIDERS.map(String::from).into(),
+            image_distance: DEFAULT_IMAGE_DISTANCE,
+            recent_window: DEFAULT_RECENT_WINDOW,
+            not_repost_emoji: String::from(DEFAULT_NOT_REPOST_EMOJI),
+            leaderboard_size: DEFAULT_LEADERBOARD_SIZE,
+        }
+    }
+}
+
+fn domains_regex(domains: &[String]) -> Regex {
+    let domains = domains.iter().map(|d| regex::escape(d)).join("|");
+    Regex::new(format!(r"https?://({domains})/?\S*").as_str()).unwrap()
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+impl ServerSettings {
+    /// Loads the settings for a server, any not set for the server are left as the default
+    pub async fn load(server_id: u64) -> Result<ServerSettings> {
+        let stored = read_only_db_async(move |db| db.get_server_settings(server_id)).await?;
+
+        let mut settings = ServerSettings::default();
+        for (name, value) in stored {
+            match SettingKey::from_name(&name) {
+                Some(key) => {
+                    if let Err(why) = settings.set(key, &value) {
+                        warn!(
+                            "ignoring invalid setting {name}={value} for server {server_id}: {why}"
+                        );
+                    }
+                }
+                None => warn!("ignoring unknown setting {name} for server {server_id}"),
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn set(&mut self, key: SettingKey, value: &str) -> Result<()> {
+        match key {
+            SettingKey::IgnoredDomains => {
+                let domains = parse_list(value);
+                self.ignored_domains_re = domains_regex(&domains);
+                self.ignored_domains = domains;
+            }
+            SettingKey::IgnoredProviders => {
+                self.ignored_providers = parse_list(value).into_iter().collect();
+            }
+            SettingKey::ImageDistance => {
+                self.image_distance = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| Error::ConstStr("value must be a whole number"))?;
+            }
+            SettingKey::RecentWindow => {
+                self.recent_window = value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|v| *v >= 0)
+                    .ok_or(Error::ConstStr("value must be a whole number of seconds"))?;
+            }
+            SettingKey::NotRepostEmoji => {
+                let emoji = value.trim();
+                if emoji.is_empty() {
+                    return Err(Error::ConstStr("value must be an emoji"));
+                }
+                self.not_repost_emoji = String::from(emoji);
+            }
+            SettingKey::LeaderboardSize => {
+                self.leaderboard_size = value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|v| (1..=MAX_LEADERBOARD_SIZE).contains(v))
+                    .ok_or(Error::ConstStr(
+                        "value must be a whole number from 1 to 1000",
+                    ))?;
+            }
+        };
+        Ok(())
+    }
+
+    pub fn get(&self, key: SettingKey) -> String {
+        match key {
+            SettingKey::IgnoredDomains => self.ignored_domains.join(","),
+            SettingKey::IgnoredProviders => self.ignored_providers.iter().sorted().join(","),
+            SettingKey::ImageDistance => self.image_distance.to_string(),
+            SettingKey::RecentWindow => self.recent_window.to_string(),
+            SettingKey::NotRepostEmoji => self.not_repost_emoji.clone(),
+            SettingKey::LeaderboardSize => self.leaderboard_size.to_string(),
+        }
+    }
+
+    /// returns true if the input link is one of the ignored domains
+    pub fn ignored_domain(&self, link: &str) -> bool {
+        !self.ignored_domains.is_empty() && self.ignored_domains_re.is_match(link)
+    }
+
+    pub fn ignored_provider(&self, provider: &str) -> bool {
+        self.ignored_providers.contains(provider)
+    }
+
+    /// returns true if the reaction is the one used to say a reply isn't a repost. Custom
+    /// emojis can be set by name or as they're written in messages, i.e. <:name:id>
+    pub fn is_not_repost_emoji(&self, emoji: &ReactionType) -> bool {
+        match emoji {
+            ReactionType::Unicode(unicode) => *unicode == self.not_repost_emoji,
+            ReactionType::Custom { id, name, .. } => {
+                let setting = self.not_repost_emoji.trim_matches(':');
+                name.as_deref() == Some(setting) || setting.ends_with(&format!(":{id}>"))
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::model::id::EmojiId;
+
+    #[test]
+    fn test_default_ignored_domains() {
+        let settings = ServerSettings::default();
+        assert!(settings.ignored_domain("https://globle-game.com"));
+        assert!(settings.ignored_domain("http://discord.com/channels/1/2/3"));
+        assert!(!settings.ignored_domain("https://discord.com/developers/docs/intro"));
+        // escaped so the . doesn't match any character
+        assert!(!settings.ignored_domain("https://heardleaapp.com"));
+    }
+
+    #[test]
+    fn test_set_ignored_domains() -> Result<()> {
+        let mut settings = ServerSettings::default();
+        settings.set(SettingKey::IgnoredDomains, " example.com, , news.com/live ")?;
+
+        assert_eq!(
+            settings.get(SettingKey::IgnoredDomains),
+            "example.com,news.com/live"
+        );
+        assert!(settings.ignored_domain("https://example.com/page"));
+        assert!(settings.ignored_domain("https://news.com/live/123"));
+        assert!(!settings.ignored_domain("https://globle-game.com"));
+
+        settings.set(SettingKey::IgnoredDomains, "")?;
+        assert!(!settings.ignored_domain("https://example.com/page"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_numbers() -> Result<()> {
+        let mut settings = ServerSettings::default();
+        settings.set(SettingKey::ImageDistance, "8")?;
+        settings.set(SettingKey::RecentWindow, "60")?;
+        settings.set(SettingKey::LeaderboardSize, "25")?;
+        assert_eq!(settings.image_distance, 8);
+        assert_eq!(settings.recent_window, 60);
+        assert_eq!(settings.leaderboard_size, 25);
+
+        assert!(settings.set(SettingKey::ImageDistance, "-1").is_err());
+        assert!(settings.set(SettingKey::RecentWindow, "-1").is_err());
+        assert!(settings.set(SettingKey::RecentWindow, "soon").is_err());
+        assert!(settings.set(SettingKey::LeaderboardSize, "0").is_err());
+        assert!(settings.set(SettingKey::LeaderboardSize, "1001").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_repost_emoji() -> Result<()> {
+        let mut settings = ServerSettings::default();
+        assert!(settings.is_not_repost_emoji(&ReactionType::Unicode(String::from("❌"))));
+        assert!(!settings.is_not_repost_emoji(&ReactionType::Unicode(String::from("👍"))));
+
+        let custom = ReactionType::Custom {
+            animated: false,
+            id: EmojiId(1234),
+            name: Some(String::from("nope")),
+        };
+        assert!(!settings.is_not_repost_emoji(&custom));
+        settings.set(SettingKey::NotRepostEmoji, ":nope:")?;
+        assert!(settings.is_not_repost_emoji(&custom));
+        settings.set(SettingKey::NotRepostEmoji, "<:other:1234>")?;
+        assert!(settings.is_not_repost_emoji(&custom));
+
+        assert!(settings.set(SettingKey::NotRepostEmoji, " ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_names() {
+        for key in SettingKey::ALL {
+            assert_eq!(SettingKey::from_name(key.name()), Some(key));
+        }
+        assert_eq!(
+            SettingKey::from_name("Image_Distance"),
+            Some(SettingKey::ImageDistance)
+        );
+        assert_eq!(SettingKey::from_name("colour"), None);
+    }
+}
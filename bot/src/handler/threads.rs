@@ -0,0 +1,192 @@
+use super::bot_read_channel_permission;
+use crate::errors::{Error, Result};
+
+use db::{writable_db_async, WriteableDb};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use reqwest::{
+    header::{AUTHORIZATION, RETRY_AFTER},
+    StatusCode,
+};
+use serenity::{
+    model::{
+        channel::{ChannelType, GuildChannel, ThreadsData},
+        id::{ChannelId, GuildId},
+        permissions::Permissions,
+        Timestamp,
+    },
+    prelude::*,
+};
+use std::time::Duration;
+
+// the most discord returns in one page of archived threads
+const ARCHIVE_PAGE_SIZE: u64 = 100;
+
+// how many times a rate limited page is retried before giving up on the channel
+const RATE_LIMIT_RETRIES: usize = 3;
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// Threads don't have permissions of their own, they're visible if their parent channel is
+pub async fn thread_visible(ctx: &Context, thread: &GuildChannel) -> bool {
+    match thread.parent_id.and_then(|id| ctx.cache.guild_channel(id)) {
+        Some(parent) => bot_read_channel_permission(ctx, &parent).await,
+        None => false,
+    }
+}
+
+/// Stores the thread as a channel with a reference to the channel it was started in
+pub async fn store_thread(ctx: &Context, thread: &GuildChannel) -> Result<()> {
+    let parent = thread
+        .parent_id
+        .ok_or(Error::ConstStr("Thread doesn't have a parent channel"))?;
+    let visible = thread_visible(ctx, thread).await;
+    let (id, server, name) = (
+        *thread.id.as_u64(),
+        *thread.guild_id.as_u64(),
+        thread.name.clone(),
+    );
+    writable_db_async(move |db| db.update_thread(id, server, &name, *parent.as_u64(), visible))
+        .await?;
+    Ok(())
+}
+
+/// Threads aren't cached with the other channels, so they're looked up separately and
+/// fetched if the cache doesn't have them either
+pub async fn get_thread(ctx: &Context, guild: GuildId, id: ChannelId) -> Result<GuildChannel> {
+    let cached = ctx.cache.guild_field(guild, |g| {
+        g.threads.iter().find(|thread| thread.id == id).cloned()
+    });
+    match cached.flatten() {
+        Some(thread) => Ok(thread),
+        None => id
+            .to_channel(ctx)
+            .await?
+            .guild()
+            .ok_or(Error::ConstStr("Channel isn't in a server")),
+    }
+}
+
+/// A page of archived threads from before the given time. serenity's routes for these append
+/// `before` without a `?` and as an id rather than a timestamp, and `Http::request` only takes
+/// serenity's own routes, so the page is requested directly and rate limits are waited out
+/// here instead.
+async fn get_archived_page(
+    ctx: &Context,
+    channel: u64,
+    kind: &str,
+    before: Timestamp,
+) -> Result<ThreadsData> {
+    let url = format!(
+        "https://discord.com/api/v10/channels/{channel}/threads/archived/{kind}?limit={ARCHIVE_PAGE_SIZE}&before={before}"
+    );
+    for _ in 0..RATE_LIMIT_RETRIES {
+        let response = CLIENT
+            .get(&url)
+            .header(AUTHORIZATION, &ctx.http.token)
+            .send()
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let wait = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .unwrap_or(1.0);
+            warn!("rate limited loading archived threads for channel {channel}, waiting {wait}s");
+            tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+            continue;
+        }
+        let body = response.error_for_status()?.bytes().await?;
+        return Ok(serde_json::from_slice(&body)?);
+    }
+    Err(Error::ConstStr(
+        "Still rate limited loading archived threads",
+    ))
+}
+
+/// Every archived thread of the given kind, "public" or "private", in a channel. Pages are
+/// newest first, so each one after the first is requested from before the oldest archive
+/// time of the last. Most channels fit in the first page, which goes through serenity.
+async fn get_archived_threads(
+    ctx: &Context,
+    channel: u64,
+    kind: &str,
+) -> Result<Vec<GuildChannel>> {
+    let mut page = if kind == "private" {
+        ctx.http
+            .get_channel_archived_private_threads(channel, None, None)
+            .await?
+    } else {
+        ctx.http
+            .get_channel_archived_public_threads(channel, None, None)
+            .await?
+    };
+    let mut threads = Vec::new();
+    loop {
+        let oldest = page
+            .threads
+            .iter()
+            .filter_map(|thread| thread.thread_metadata?.archive_timestamp)
+            .min();
+        threads.extend(page.threads);
+        match oldest {
+            Some(oldest) if page.has_more => {
+                page = get_archived_page(ctx, channel, kind, oldest).await?;
+            }
+            _ => break,
+        }
+    }
+    Ok(threads)
+}
+
+/// Stores the active threads in a server, along with the archived threads in each of the
+/// channels. Their history is loaded like any other channel's when the server is backfilled.
+pub async fn load_threads(
+    ctx: &Context,
+    guild: GuildId,
+    channels: impl Iterator<Item = &GuildChannel>,
+) -> Result<()> {
+    let mut threads = guild.get_active_threads(ctx).await?.threads;
+    for channel in channels {
+        if !matches!(
+            channel.kind,
+            ChannelType::Text | ChannelType::News | ChannelType::Forum
+        ) {
+            continue;
+        }
+        // archives of channels the bot can't read would only be refused
+        if !bot_read_channel_permission(ctx, channel).await {
+            continue;
+        }
+        let id = *channel.id.as_u64();
+        match get_archived_threads(ctx, id, "public").await {
+            Ok(archived) => threads.extend(archived),
+            Err(why) => warn!("failed to load archived threads for channel {id} {why:?}"),
+        }
+        // private archives also need the manage threads permission
+        let current_user = ctx.cache.current_user().id;
+        let manage_threads = channel
+            .permissions_for_user(ctx, current_user)
+            .map_or(false, |permissions| {
+                permissions.contains(Permissions::MANAGE_THREADS)
+            });
+        if manage_threads {
+            match get_archived_threads(ctx, id, "private").await {
+                Ok(archived) => threads.extend(archived),
+                Err(why) => {
+                    warn!("failed to load private archived threads for channel {id} {why:?}")
+                }
+            }
+        }
+    }
+
+    info!("found {} threads in server {guild}", threads.len());
+    for thread in threads {
+        if let Err(why) = store_thread(ctx, &thread).await {
+            warn!("failed to store thread {} {why:?}", thread.id);
+        }
+    }
+    Ok(())
+}
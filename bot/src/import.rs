@@ -0,0 +1,300 @@
+use crate::errors::{Error, Result};
+use crate::handler::images::store_image_files;
+use crate::handler::links::{get_filtered_links, store_links};
+use crate::handler::settings::ServerSettings;
+
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
+use log::{info, warn};
+use serde::Deserialize;
+use serenity::model::id::MessageId;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// log progress every this many messages, exports can be very large
+const PROGRESS_INTERVAL: usize = 1000;
+
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+
+// the parts of a DiscordChatExporter json export we use, ids are strings in the export
+
+#[derive(Debug, Deserialize)]
+struct Export {
+    guild: ExportGuild,
+    channel: ExportChannel,
+    messages: Vec<ExportMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportGuild {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportChannel {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    /// the parent channel for threads
+    category_id: Option<String>,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportMessage {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    content: String,
+    author: ExportAuthor,
+    #[serde(default)]
+    attachments: Vec<ExportAttachment>,
+    #[serde(default)]
+    embeds: Vec<ExportEmbed>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportAuthor {
+    id: String,
+    name: String,
+    discriminator: String,
+    is_bot: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportAttachment {
+    id: String,
+    /// a path relative to the export if media was downloaded with it
+    url: String,
+    file_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportEmbed {
+    image: Option<ExportImage>,
+    thumbnail: Option<ExportImage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportImage {
+    url: String,
+}
+
+#[derive(Debug, Default)]
+struct ImportStats {
+    imported: usize,
+    skipped: usize,
+    failed: usize,
+    images: usize,
+    missing_images: usize,
+}
+
+fn parse_id(id: &str) -> Result<u64> {
+    id.parse()
+        .map_err(|_| Error::ConstStr("Export has an id that isn't a number"))
+}
+
+fn is_image_file(file_name: &str) -> bool {
+    Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str())
+        })
+}
+
+/// The file for media downloaded with the export, None if the export only links to it
+fn local_file(export_dir: &Path, url: &str) -> Option<PathBuf> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return None;
+    }
+    Some(export_dir.join(url)).filter(|path| path.is_file())
+}
+
+/// Every image in the message as (url, file), images are stored under the url they had on
+/// discord when it's known
+fn message_images(
+    msg: &ExportMessage,
+    channel_id: u64,
+    export_dir: &Path,
+) -> Vec<(String, Option<PathBuf>)> {
+    let attachments = msg
+        .attachments
+        .iter()
+        .filter(|a| is_image_file(&a.file_name))
+        .map(|a| {
+            let url = format!(
+                "https://cdn.discordapp.com/attachments/{channel_id}/{}/{}",
+                a.id, a.file_name
+            );
+            (url, local_file(export_dir, &a.url))
+        });
+    let embeds = msg
+        .embeds
+        .iter()
+        .filter_map(|e| e.image.as_ref().or(e.thumbnail.as_ref()))
+        .map(|image| (image.url.clone(), local_file(export_dir, &image.url)));
+    attachments.chain(embeds).collect()
+}
+
+async fn import_message(
+    msg: &ExportMessage,
+    server_id: u64,
+    channel_id: u64,
+    export_dir: &Path,
+    settings: &ServerSettings,
+    stats: &mut ImportStats,
+) -> Result<()> {
+    // same as what's processed live, see regular_text_msg
+    if msg.author.is_bot || (msg.kind != "Default" && msg.kind != "Reply") {
+        stats.skipped += 1;
+        return Ok(());
+    }
+    let (msg_id, author_id) = (parse_id(&msg.id)?, parse_id(&msg.author.id)?);
+    if read_only_db_async(move |db| db.is_opted_out(author_id)).await? {
+        stats.skipped += 1;
+        return Ok(());
+    }
+
+    let existing = read_only_db_async(move |db| db.get_message(MessageId(msg_id))).await?;
+    let (author_name, discriminator) = (
+        msg.author.name.clone(),
+        msg.author.discriminator.parse().unwrap_or_default(),
+    );
+    writable_db_async(move |db| {
+        db.add_user(author_id, &author_name, false, discriminator)?;
+        db.add_message(MessageId(msg_id), channel_id, server_id, author_id)
+    })
+    .await?;
+
+    // anything already processed is skipped so the same export can be imported again
+    if !existing.as_ref().map_or(false, |m| m.is_repost_parsed()) {
+        let links = get_filtered_links(&msg.content, settings);
+        store_links(links, server_id, msg_id, false).await?;
+    }
+    if !existing.as_ref().map_or(false, |m| m.is_embed_parsed()) {
+        let mut files = Vec::new();
+        for (url, file) in message_images(msg, channel_id, export_dir) {
+            match file {
+                Some(path) => files.push((url, path)),
+                None => stats.missing_images += 1,
+            }
+        }
+        stats.images += files.len();
+        store_image_files(msg_id, server_id, files, settings).await?;
+    }
+
+    writable_db_async(move |db| db.mark_message_all_checked(MessageId(msg_id))).await?;
+    stats.imported += 1;
+    Ok(())
+}
+
+async fn import_file(path: &Path) -> Result<ImportStats> {
+    info!("importing {}", path.display());
+    let export: Export = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let export_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let (server_id, channel_id) = (parse_id(&export.guild.id)?, parse_id(&export.channel.id)?);
+    let parent = match &export.channel.category_id {
+        Some(id) if export.channel.kind.contains("Thread") => Some(parse_id(id)?),
+        _ => None,
+    };
+    let (server_name, channel_name) = (export.guild.name.clone(), export.channel.name.clone());
+    writable_db_async(move |db| {
+        db.update_server(server_id, &Some(server_name))?;
+        match parent {
+            Some(parent) => db.update_thread(channel_id, server_id, &channel_name, parent, true)?,
+            None => db.update_channel(channel_id, server_id, &channel_name, true)?,
+        }
+        Ok(())
+    })
+    .await?;
+
+    let settings = ServerSettings::load(server_id).await?;
+    let mut stats = ImportStats::default();
+    for (i, msg) in export.messages.iter().enumerate() {
+        let ret = import_message(
+            msg, server_id, channel_id, export_dir, &settings, &mut stats,
+        )
+        .await;
+        if let Err(why) = ret {
+            warn!("failed to import message {} with error {why:?}", msg.id);
+            stats.failed += 1;
+        }
+        if (i + 1) % PROGRESS_INTERVAL == 0 {
+            info!(
+                "imported {}/{} messages from #{}",
+                i + 1,
+                export.messages.len(),
+                export.channel.name
+            );
+        }
+    }
+    Ok(stats)
+}
+
+/// Imports DiscordChatExporter json exports, for loading history without going through the
+/// discord api. Images are hashed from the files downloaded with the export, images that
+/// weren't downloaded are skipped.
+pub async fn run(paths: &[String]) -> Result<()> {
+    for path in paths {
+        let stats = import_file(Path::new(path)).await?;
+        info!(
+            "finished importing {path}: {} messages imported, {} skipped, {} failed, {} images hashed, {} images not in the export",
+            stats.imported, stats.skipped, stats.failed, stats.images, stats.missing_images
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_export() -> Result<()> {
+        let export: Export = serde_json::from_value(serde_json::json!({
+            "guild": {"id": "1", "name": "server", "iconUrl": ""},
+            "channel": {"id": "2", "type": "GuildTextChat", "categoryId": "3", "name": "general"},
+            "messages": [{
+                "id": "10",
+                "type": "Default",
+                "timestamp": "2022-01-01T00:00:00+00:00",
+                "content": "https://example.com",
+                "author": {"id": "4", "name": "user", "discriminator": "0001", "isBot": false},
+                "attachments": [
+                    {"id": "20", "url": "export_Files/cat.png", "fileName": "cat.png", "fileSizeBytes": 1},
+                    {"id": "21", "url": "export_Files/notes.txt", "fileName": "notes.txt", "fileSizeBytes": 1}
+                ],
+                "embeds": [{"title": "", "thumbnail": {"url": "https://example.com/thumb.jpg"}}]
+            }],
+            "messageCount": 1
+        }))?;
+        assert_eq!(export.messages.len(), 1);
+
+        let images = message_images(&export.messages[0], 2, Path::new("/nonexistent"));
+        assert_eq!(
+            images,
+            vec![
+                (
+                    String::from("https://cdn.discordapp.com/attachments/2/20/cat.png"),
+                    None
+                ),
+                (String::from("https://example.com/thumb.jpg"), None),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_image_file() {
+        assert!(is_image_file("cat.PNG"));
+        assert!(is_image_file("photo.jpeg"));
+        assert!(!is_image_file("notes.txt"));
+        assert!(!is_image_file("png"));
+    }
+}
@@ -4,8 +4,11 @@
     clippy::option_if_let_else
 )]
 
+mod config;
 mod errors;
+mod export;
 mod handler;
+mod import;
 mod structs;
 
 use log::LevelFilter;
@@ -21,6 +24,19 @@ use std::process;
 use db::migrate;
 use handler::Handler;
 
+fn init_db() {
+    let config = match config::load_db_config() {
+        Ok(config) => config,
+        Err(why) => {
+            error!("Failed to load db config, exiting {why:?}");
+            process::exit(-1);
+        }
+    };
+    if db::init(config).is_err() {
+        warn!("db config was already set, ignoring loaded config");
+    }
+}
+
 fn migrate_db() {
     match migrate() {
         Ok(_) => info!("sucessfully loaded and migrated db"),
@@ -42,14 +58,40 @@ async fn main() {
         .with_utc_offset(UtcOffset::from_hms(-4, 0, 0).unwrap())
         .init()
         .unwrap();
+    // configure and migrate the db
+    init_db();
+    migrate_db();
+
+    // `bot import <export.json>...` loads exported history instead of running the bot
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if args.first().map(String::as_str) == Some("import") {
+        if args.len() < 2 {
+            error!("Usage: bot import <export.json>...");
+            process::exit(-1);
+        }
+        if let Err(why) = import::run(&args[1..]).await {
+            error!("Failed to import, exiting {why:?}");
+            process::exit(-1);
+        }
+        return;
+    }
+
+    // `bot export <server id> <file> <kind> [filters]` writes out stored data instead of running the bot
+    if args.first().map(String::as_str) == Some("export") {
+        if let Err(why) = export::run(&args[1..]).await {
+            error!("Failed to export, exiting {why}");
+            process::exit(-1);
+        }
+        return;
+    }
+
     // Configure the client with your Discord bot token in the environment.
     let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
-    // migrate the db
-    migrate_db();
 
     let intents = GatewayIntents::GUILDS
         .union(GatewayIntents::GUILD_MEMBERS)
         .union(GatewayIntents::GUILD_MESSAGES)
+        .union(GatewayIntents::GUILD_MESSAGE_REACTIONS)
         .union(GatewayIntents::DIRECT_MESSAGES)
         .union(GatewayIntents::MESSAGE_CONTENT);
 
@@ -1,17 +1,55 @@
 use crate::errors::{Error, Result};
 
-use db::{read_only_db_call, writable_db_call, ReadOnlyDb, WriteableDb};
+use db::{read_only_db_async, writable_db_async, ReadOnlyDb, WriteableDb};
 use log::info;
 use serde_json::json;
 use serenity::builder::ParseValue;
 use serenity::model;
-use serenity::model::channel::MessageReference;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::channel::{AttachmentType, MessageReference, ReactionType};
 use serenity::prelude::Context;
+use std::borrow::Cow;
+
+/// Discord won't send messages longer than this many characters
+const MAX_MESSAGE_LENGTH: usize = 2000;
+
+// room left at the bottom of each page for saying how to get to the next one
+const PAGE_FOOTER_LENGTH: usize = 64;
+
+/// Splits a list into pages that each fit in one message along with the header above them.
+/// There's always at least one page, even when the list is empty.
+fn paginate(header: &str, lines: &[String]) -> Vec<String> {
+    let budget = MAX_MESSAGE_LENGTH - PAGE_FOOTER_LENGTH - header.chars().count();
+    let mut pages = Vec::new();
+    let mut page = String::from(header);
+    let mut length = 0;
+    for line in lines {
+        // a line too long for a page of its own is cut short rather than left out
+        let line = line.chars().take(budget - 1).collect::<String>();
+        let line_length = line.chars().count() + 1;
+        if length + line_length > budget {
+            pages.push(page);
+            page = String::from(header);
+            length = 0;
+        }
+        page.push('\n');
+        page.push_str(&line);
+        length += line_length;
+    }
+    pages.push(page);
+    pages
+}
 
 #[derive(Debug)]
 pub enum ReplyContents {
     String(String),
     ConstStr(&'static str),
+    /// Message with a file attached
+    File {
+        message: String,
+        name: String,
+        data: Vec<u8>,
+    },
 }
 
 #[derive(Debug)]
@@ -19,6 +57,12 @@ pub enum ReplyType<'a> {
     Message(&'a model::channel::Message),
     Channel(model::id::ChannelId),
     MessageId(model::id::MessageId, model::id::ChannelId),
+    /// Response to a slash command, the interaction must have already been deferred
+    Interaction(&'a ApplicationCommandInteraction),
+    /// React to the message with the contents instead of replying
+    Reaction(model::id::MessageId, model::id::ChannelId),
+    /// Reply to a message in a DM, nothing sent in DMs is stored so neither is the reply
+    DirectMessage(model::id::MessageId, model::id::ChannelId),
 }
 
 #[derive(Debug)]
@@ -42,10 +86,54 @@ impl Reply<'_> {
         }
     }
 
+    /// One page of a long list, with how to get to the next page at the bottom. Pages count
+    /// from 1, asking for a page past the end gets the last page.
+    pub fn new_page<'a>(
+        header: &str,
+        lines: &[String],
+        page: usize,
+        place: ReplyType<'a>,
+    ) -> Reply<'a> {
+        let pages = paginate(header, lines);
+        let page = page.clamp(1, pages.len());
+        let mut message = pages[page - 1].clone();
+        if page < pages.len() {
+            message.push_str(&format!(
+                "\nPage {page}/{}, add `page {}` to see the next",
+                pages.len(),
+                page + 1
+            ));
+        } else if pages.len() > 1 {
+            message.push_str(&format!("\nPage {page}/{}", pages.len()));
+        }
+        Reply::new(message, place)
+    }
+
+    pub const fn new_file<'a>(
+        message: String,
+        name: String,
+        data: Vec<u8>,
+        place: ReplyType<'a>,
+    ) -> Reply<'a> {
+        Reply {
+            message: ReplyContents::File {
+                message,
+                name,
+                data,
+            },
+            place,
+        }
+    }
+
     pub async fn send(&self, ctx: &Context) -> Result<()> {
         let resp = match &self.message {
             ReplyContents::String(inner) => inner,
             ReplyContents::ConstStr(inner) => *inner,
+            ReplyContents::File {
+                message,
+                name,
+                data,
+            } => return self.send_file(ctx, message, name, data).await,
         };
 
         match &self.place {
@@ -53,15 +141,19 @@ impl Reply<'_> {
                 channel.say(ctx, resp).await?;
             }
             ReplyType::Message(msg) => {
-                if let Some(db_reply) = read_only_db_call(|db| db.get_reply(*msg.id.as_u64()))? {
+                let msg_id = *msg.id.as_u64();
+                if let Some(db_reply) = read_only_db_async(move |db| db.get_reply(msg_id)).await? {
                     edit_reply(ctx, &db_reply, resp).await?;
                 } else {
                     let reply = msg.reply(ctx, resp).await?;
-                    self.store_reply(reply.id)?;
+                    self.store_reply(reply.id).await?;
                 }
             }
             ReplyType::MessageId(msg_id, channel_id) => {
-                if let Some(db_reply) = read_only_db_call(|db| db.get_reply(*msg_id.as_u64()))? {
+                let replied_id = *msg_id.as_u64();
+                if let Some(db_reply) =
+                    read_only_db_async(move |db| db.get_reply(replied_id)).await?
+                {
                     edit_reply(ctx, &db_reply, resp).await?;
                 } else {
                     // The following code is essentially entirely copied from serenity (the library being used)
@@ -80,26 +172,82 @@ impl Reply<'_> {
                             builder.content(resp)
                         })
                         .await?;
-                    self.store_reply(reply.id)?;
+                    self.store_reply(reply.id).await?;
                 }
             }
+            ReplyType::DirectMessage(msg_id, channel_id) => {
+                let reply = channel_id
+                    .send_message(ctx, |builder| {
+                        builder
+                            .reference_message(MessageReference::from((*channel_id, *msg_id)))
+                            .content(resp)
+                    })
+                    .await?;
+                self.store_reply(reply.id).await?;
+            }
+            ReplyType::Interaction(interaction) => {
+                interaction
+                    .edit_original_interaction_response(ctx, |r| r.content(resp))
+                    .await?;
+            }
+            ReplyType::Reaction(msg_id, channel_id) => {
+                channel_id
+                    .create_reaction(ctx, *msg_id, ReactionType::Unicode(resp.to_string()))
+                    .await?;
+            }
         };
 
         Ok(())
     }
 
-    fn store_reply(&self, reply_id: model::id::MessageId) -> Result<()> {
+    /// Files are only sent for commands, so unlike other replies they're never stored or
+    /// edited later
+    async fn send_file(&self, ctx: &Context, message: &str, name: &str, data: &[u8]) -> Result<()> {
+        let file = AttachmentType::Bytes {
+            data: Cow::from(data),
+            filename: String::from(name),
+        };
+        match &self.place {
+            ReplyType::Channel(channel) => {
+                channel
+                    .send_message(ctx, |m| m.content(message).add_file(file))
+                    .await?;
+            }
+            ReplyType::Message(msg) => {
+                msg.channel_id
+                    .send_message(ctx, |m| {
+                        m.reference_message(*msg).content(message).add_file(file)
+                    })
+                    .await?;
+            }
+            // the deferred response can't have files added, but the first follow up replaces it
+            ReplyType::Interaction(interaction) => {
+                interaction
+                    .create_followup_message(ctx, |f| f.content(message).add_file(file))
+                    .await?;
+            }
+            ReplyType::MessageId(..) | ReplyType::Reaction(..) | ReplyType::DirectMessage(..) => {
+                return Err(Error::ConstStr("Files can only be sent to commands"));
+            }
+        };
+        Ok(())
+    }
+
+    async fn store_reply(&self, reply_id: model::id::MessageId) -> Result<()> {
         let (replied_to, channel_id) = match &self.place {
             ReplyType::Message(msg) => Ok((*msg.id.as_u64(), *msg.channel_id.as_u64())),
             ReplyType::MessageId(msg_id, channel_id) => {
                 Ok((*msg_id.as_u64(), *channel_id.as_u64()))
             }
+            // the message and channel aren't stored, so there's nothing for the reply to refer to
+            ReplyType::DirectMessage(..) => return Ok(()),
             _ => Err(Error::ConstStr(
                 "Can't store reply if not replying to a message",
             )),
         }?;
 
-        writable_db_call(|db| db.add_reply(*reply_id.as_u64(), channel_id, replied_to))?;
+        writable_db_async(move |db| db.add_reply(*reply_id.as_u64(), channel_id, replied_to))
+            .await?;
         Ok(())
     }
 }
@@ -115,3 +263,80 @@ async fn edit_reply(ctx: &Context, db_reply: &db::structs::Reply, content: &str)
         .await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db::DbConfig;
+    use model::id::{ChannelId, MessageId};
+    use std::path::PathBuf;
+
+    fn contents(reply: &Reply<'_>) -> String {
+        match &reply.message {
+            ReplyContents::String(message) => message.clone(),
+            _ => String::new(),
+        }
+    }
+
+    #[test]
+    fn test_paginate() {
+        assert_eq!(paginate("header", &[]), vec!["header"]);
+
+        let lines = (0..200).map(|i| format!("{i:>19}")).collect::<Vec<_>>();
+        let pages = paginate("header", &lines);
+        assert!(pages.len() > 1);
+        assert!(pages
+            .iter()
+            .all(|page| page.chars().count() + PAGE_FOOTER_LENGTH <= MAX_MESSAGE_LENGTH));
+        assert!(pages.iter().all(|page| page.starts_with("header\n")));
+        // every line ends up on exactly one page
+        assert_eq!(
+            pages
+                .iter()
+                .map(|page| page.lines().count() - 1)
+                .sum::<usize>(),
+            lines.len()
+        );
+
+        let long = vec![String::from("a").repeat(5000)];
+        assert!(paginate("header", &long)[0].chars().count() < MAX_MESSAGE_LENGTH);
+    }
+
+    #[test]
+    fn test_new_page() {
+        let place = ReplyType::Channel(model::id::ChannelId(1));
+        let lines = (0..200).map(|i| format!("{i:>19}")).collect::<Vec<_>>();
+        let pages = paginate("header", &lines).len();
+
+        let first = contents(&Reply::new_page("header", &lines, 1, place));
+        assert!(first.ends_with(&format!("Page 1/{pages}, add `page 2` to see the next")));
+        assert!(first.chars().count() <= MAX_MESSAGE_LENGTH);
+
+        let place = ReplyType::Channel(model::id::ChannelId(1));
+        let last = contents(&Reply::new_page("header", &lines, 100, place));
+        assert!(last.ends_with(&format!("Page {pages}/{pages}")));
+
+        let place = ReplyType::Channel(model::id::ChannelId(1));
+        let short = contents(&Reply::new_page("header", &lines[..2], 1, place));
+        assert_eq!(short.lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_direct_message_reply_not_stored() -> Result<()> {
+        // nothing else in the bot's tests uses the shared db, so this sets it up
+        let _ = db::init(DbConfig {
+            path: PathBuf::from("test_direct_message_reply_not_stored"),
+            in_memory: true,
+            ..DbConfig::default()
+        });
+        db::migrate()?;
+
+        let reply = Reply::new_const(
+            "reply",
+            ReplyType::DirectMessage(MessageId(1), ChannelId(2)),
+        );
+        reply.store_reply(MessageId(3)).await?;
+        assert!(read_only_db_async(|db| db.get_reply(1)).await?.is_none());
+        Ok(())
+    }
+}
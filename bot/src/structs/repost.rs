@@ -1,10 +1,11 @@
 use crate::structs::reply::{Reply, ReplyType};
 
 use chrono::{DateTime, Utc};
+use db::structs::ChannelMode;
 use db::structs::Message;
 use humantime::format_duration;
 use itertools::Itertools;
-use log::info;
+use log::{info, warn};
 use serenity::model;
 use std::collections::{BTreeMap, HashSet};
 use std::vec::Vec;
@@ -39,6 +40,18 @@ impl RepostSet {
         }
     }
 
+    /// Builds the set from stored repost events, as returned by get_repost_originals
+    pub fn from_originals(originals: &[(Message, String)]) -> RepostSet {
+        let mut set = RepostSet::new();
+        for (msg, kind) in originals {
+            match RepostType::from_kind(kind) {
+                Some(repost_type) => set.add(*msg, repost_type),
+                None => warn!("unknown repost kind {kind} for original {}", msg.id),
+            }
+        }
+        set
+    }
+
     pub fn add(&mut self, msg: Message, repost_type: RepostType) {
         self.reposts
             .entry(msg)
@@ -65,17 +78,38 @@ impl RepostSet {
         msg_id: &'a model::id::MessageId,
         channel_id: &'a model::id::ChannelId,
         msg_created_at: DateTime<Utc>,
+        mode: ChannelMode,
     ) -> Option<Reply<'a>> {
-        self.generate_reply(msg_created_at)
-            .map(|x| Reply::new(x, ReplyType::MessageId(*msg_id, *channel_id)))
+        match mode {
+            ChannelMode::Reply => self
+                .generate_reply(msg_created_at)
+                .map(|x| Reply::new(x, ReplyType::MessageId(*msg_id, *channel_id))),
+            ChannelMode::React => self.generate_reaction(*msg_id, *channel_id),
+            ChannelMode::Off | ChannelMode::Silent => None,
+        }
     }
 
     pub fn generate_reply_for_message<'a>(
         &self,
         msg: &'a serenity::model::prelude::Message,
+        mode: ChannelMode,
     ) -> Option<Reply<'a>> {
-        self.generate_reply(*msg.id.created_at())
-            .map(|x| Reply::new(x, ReplyType::Message(msg)))
+        match mode {
+            ChannelMode::Reply => self
+                .generate_reply(*msg.id.created_at())
+                .map(|x| Reply::new(x, ReplyType::Message(msg))),
+            ChannelMode::React => self.generate_reaction(msg.id, msg.channel_id),
+            ChannelMode::Off | ChannelMode::Silent => None,
+        }
+    }
+
+    fn generate_reaction<'a>(
+        &self,
+        msg_id: model::id::MessageId,
+        channel_id: model::id::ChannelId,
+    ) -> Option<Reply<'a>> {
+        (!self.reposts.is_empty())
+            .then(|| Reply::new_const("🚨", ReplyType::Reaction(msg_id, channel_id)))
     }
 
     fn generate_reply(&self, reply_to_created_at: DateTime<Utc>) -> Option<String> {
@@ -112,6 +146,14 @@ impl RepostSet {
 }
 
 impl RepostType {
+    fn from_kind(kind: &str) -> Option<RepostType> {
+        match kind {
+            "link" => Some(RepostType::Link),
+            "image" => Some(RepostType::Image),
+            _ => None,
+        }
+    }
+
     const fn text_long(&self) -> &str {
         match self {
             RepostType::Link => "LINK",
@@ -336,6 +378,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_from_originals() {
+        let msg = get_message(1, 1, 1, get_datetime(1, 0, 0));
+        let set = RepostSet::from_originals(&[
+            (msg, String::from("link")),
+            (msg, String::from("image")),
+            (
+                get_message(2, 1, 1, get_datetime(1, 0, 0)),
+                String::from("video"),
+            ),
+        ]);
+        assert_eq!(set.len(), 1);
+        assert_eq!(set.types.len(), 2);
+    }
+
     #[test]
     fn test_single_repost_image_link() {
         let mut set = RepostSet::new();
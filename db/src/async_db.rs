@@ -0,0 +1,38 @@
+use crate::errors::Result;
+use crate::{get_db, ReadOnlyConn, WriteableConn};
+
+use std::panic;
+use tokio::task::{self, JoinError};
+
+// Anything other than a panic means the runtime is shutting down, in which case
+// there isn't a caller left to hand an error to
+fn resume_join_error(why: JoinError) -> ! {
+    if why.is_panic() {
+        panic::resume_unwind(why.into_panic())
+    }
+    panic!("db task cancelled: {why}")
+}
+
+/// Async version of read_only_db_call, runs the call on tokio's blocking thread
+/// pool so slow queries don't stall the async worker threads
+pub async fn read_only_db_async<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce(ReadOnlyConn<'static>) -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    task::spawn_blocking(move || f(get_db()?.read_only()?))
+        .await
+        .unwrap_or_else(|why| resume_join_error(why))
+}
+
+/// Async version of writable_db_call, runs the call on tokio's blocking thread
+/// pool so waiting on the writer doesn't stall the async worker threads
+pub async fn writable_db_async<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce(WriteableConn<'static>) -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    task::spawn_blocking(move || f(get_db()?.writeable()?))
+        .await
+        .unwrap_or_else(|why| resume_join_error(why))
+}
@@ -0,0 +1,206 @@
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+/// SQLite journal modes, see https://www.sqlite.org/pragma.html#pragma_journal_mode
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+
+impl JournalMode {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Truncate => "TRUNCATE",
+            JournalMode::Persist => "PERSIST",
+            JournalMode::Memory => "MEMORY",
+            JournalMode::Wal => "WAL",
+            JournalMode::Off => "OFF",
+        }
+    }
+}
+
+impl FromStr for JournalMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "DELETE" => Ok(JournalMode::Delete),
+            "TRUNCATE" => Ok(JournalMode::Truncate),
+            "PERSIST" => Ok(JournalMode::Persist),
+            "MEMORY" => Ok(JournalMode::Memory),
+            "WAL" => Ok(JournalMode::Wal),
+            "OFF" => Ok(JournalMode::Off),
+            _ => Err(()),
+        }
+    }
+}
+
+/// SQLite synchronous levels, see https://www.sqlite.org/pragma.html#pragma_synchronous
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+            Synchronous::Extra => "EXTRA",
+        }
+    }
+}
+
+impl FromStr for Synchronous {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "OFF" => Ok(Synchronous::Off),
+            "NORMAL" => Ok(Synchronous::Normal),
+            "FULL" => Ok(Synchronous::Full),
+            "EXTRA" => Ok(Synchronous::Extra),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownKey(String),
+    InvalidValue { key: String, value: String },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownKey(key) => write!(f, "unknown db config key {key}"),
+            ConfigError::InvalidValue { key, value } => {
+                write!(f, "invalid value {value} for db config key {key}")
+            }
+        }
+    }
+}
+
+impl StdError for ConfigError {}
+
+/// Settings used whenever a connection to the database is opened
+#[derive(Debug, Clone)]
+pub struct DbConfig {
+    pub path: PathBuf,
+    pub in_memory: bool,
+    pub busy_timeout: Duration,
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+    /// Upper bound on concurrently open reader connections
+    pub max_readers: usize,
+    /// Number of prepared statements cached per connection
+    pub statement_cache_capacity: usize,
+    /// Times a write transaction is retried if the db is busy
+    pub write_retries: u32,
+    /// Wait before the first retry, doubles on each retry after
+    pub retry_backoff: Duration,
+}
+
+impl Default for DbConfig {
+    fn default() -> DbConfig {
+        DbConfig {
+            path: PathBuf::from("./repost.db3"),
+            in_memory: false,
+            busy_timeout: Duration::from_secs(5),
+            // WAL lets the readers carry on whilst the writer is writing
+            journal_mode: JournalMode::Wal,
+            synchronous: Synchronous::Normal,
+            max_readers: 4,
+            statement_cache_capacity: 32,
+            write_retries: 5,
+            retry_backoff: Duration::from_millis(50),
+        }
+    }
+}
+
+impl DbConfig {
+    /// Sets a single value by key, as read from the environment or a config file.
+    /// Keys are case insensitive, i.e. both `path` and `PATH` set the db path.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let value = value.trim();
+        let invalid = || ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "path" => self.path = PathBuf::from(value),
+            "in_memory" => self.in_memory = value.parse().map_err(|_| invalid())?,
+            "busy_timeout_ms" => {
+                self.busy_timeout = Duration::from_millis(value.parse().map_err(|_| invalid())?)
+            }
+            "journal_mode" => self.journal_mode = value.parse().map_err(|_| invalid())?,
+            "synchronous" => self.synchronous = value.parse().map_err(|_| invalid())?,
+            "max_readers" => self.max_readers = value.parse().map_err(|_| invalid())?,
+            "statement_cache_capacity" => {
+                self.statement_cache_capacity = value.parse().map_err(|_| invalid())?
+            }
+            "write_retries" => self.write_retries = value.parse().map_err(|_| invalid())?,
+            "retry_backoff_ms" => {
+                self.retry_backoff = Duration::from_millis(value.parse().map_err(|_| invalid())?)
+            }
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_values() -> Result<(), ConfigError> {
+        let mut config = DbConfig::default();
+        config.set("PATH", "/data/repost.db3")?;
+        config.set("in_memory", "true")?;
+        config.set("busy_timeout_ms", "250")?;
+        config.set("journal_mode", "wal")?;
+        config.set("Synchronous", "NORMAL")?;
+
+        assert_eq!(config.path, PathBuf::from("/data/repost.db3"));
+        assert!(config.in_memory);
+        assert_eq!(config.busy_timeout, Duration::from_millis(250));
+        assert_eq!(config.journal_mode, JournalMode::Wal);
+        assert_eq!(config.synchronous, Synchronous::Normal);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_invalid() {
+        let mut config = DbConfig::default();
+        assert!(matches!(
+            config.set("journal_mode", "sometimes"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.set("busy_timeout_ms", "-1"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.set("colour", "blue"),
+            Err(ConfigError::UnknownKey(_))
+        ));
+    }
+}
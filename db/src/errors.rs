@@ -0,0 +1,44 @@
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display},
+    result,
+};
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Rusqlite(rusqlite::Error),
+    /// The db was still busy or locked after retrying a write the maximum
+    /// number of times, last holds the error from the final attempt
+    RetriesExhausted {
+        attempts: u32,
+        last: rusqlite::Error,
+    },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rusqlite(inner) => fmt::Display::fmt(&inner, f),
+            Error::RetriesExhausted { attempts, last } => {
+                write!(f, "db still busy after {attempts} attempts: {last}")
+            }
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Rusqlite(inner) => Some(inner),
+            Error::RetriesExhausted { last, .. } => Some(last),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Error {
+        Error::Rusqlite(e)
+    }
+}
@@ -0,0 +1,29 @@
+use crate::errors::Result;
+use crate::pool::Db;
+use crate::{DbConfig, WriteableDb};
+
+use std::path::PathBuf;
+
+/// A migrated in memory db, each name gets its own so tests don't see each other's data
+pub fn get_db(name: &str, max_readers: usize) -> Result<Db> {
+    let db = Db::new(DbConfig {
+        path: PathBuf::from(name),
+        in_memory: true,
+        max_readers,
+        ..DbConfig::default()
+    })?;
+    db.migrate()?;
+    Ok(db)
+}
+
+/// A db with server 1, its visible channel 2 named "channel" and user 4 named "poster"
+pub fn seeded_db(name: &str) -> Result<Db> {
+    let db = get_db(name, 1)?;
+    {
+        let writer = db.writeable()?;
+        writer.update_server(1, &None)?;
+        writer.update_channel(2, 1, "channel", true)?;
+        writer.add_user(4, "poster", false, 1)?;
+    }
+    Ok(db)
+}
@@ -1,141 +1,88 @@
+mod async_db;
+mod config;
+mod errors;
+#[cfg(test)]
+mod fixtures;
 mod migrations;
+mod pool;
 mod queries;
 mod read_only_db;
+mod retry;
 pub mod structs;
 mod writeable_db;
 
+pub use async_db::{read_only_db_async, writable_db_async};
+pub use config::{ConfigError, DbConfig, JournalMode, Synchronous};
+pub use errors::{Error, Result};
+pub use pool::{Db, ReadOnlyConn, WriteableConn};
 pub use read_only_db::ReadOnlyDb;
 pub use writeable_db::WriteableDb;
 
-use rusqlite::{Connection, OpenFlags, Result};
+use once_cell::sync::OnceCell;
 
 pub(crate) mod connections {
-    use rusqlite::{Connection, Params, Result};
+    use crate::errors::Result;
+    use crate::retry::RetryPolicy;
+    use rusqlite::{Connection, Params};
 
     pub trait GetConnectionImmutable {
         fn get_connection(&self) -> &Connection;
 
         #[inline(always)]
         fn execute<P: Params>(&self, sql: &str, params: P) -> Result<()> {
-            self.get_connection().execute(sql, params)?;
+            self.get_connection().prepare_cached(sql)?.execute(params)?;
             Ok(())
         }
     }
 
     pub trait GetConnectionMutable {
         fn get_mutable_connection(&mut self) -> &mut Connection;
-    }
-}
-
-pub struct ReadOnlyConn {
-    conn: Connection,
-}
 
-impl connections::GetConnectionImmutable for ReadOnlyConn {
-    #[inline]
-    fn get_connection(&self) -> &Connection {
-        &self.conn
+        fn retry_policy(&self) -> RetryPolicy;
     }
 }
 
-impl ReadOnlyDb for ReadOnlyConn {}
-
-pub struct WriteableConn {
-    conn: Connection,
-}
-
-impl connections::GetConnectionImmutable for WriteableConn {
-    #[inline]
-    fn get_connection(&self) -> &Connection {
-        &self.conn
-    }
-}
+static CONFIG: OnceCell<DbConfig> = OnceCell::new();
+static DB: OnceCell<Db> = OnceCell::new();
 
-impl connections::GetConnectionMutable for WriteableConn {
-    #[inline]
-    fn get_mutable_connection(&mut self) -> &mut Connection {
-        &mut self.conn
-    }
+/// Sets the config used to open the shared db. Must be called before the db is
+/// first used, returns the passed config back if already set.
+pub fn init(config: DbConfig) -> std::result::Result<(), DbConfig> {
+    CONFIG.set(config)
 }
 
-impl ReadOnlyDb for WriteableConn {}
-
-impl WriteableDb for WriteableConn {}
-
-const DB_PATH: &str = "./repost.db3";
-const IN_MEMORY_DB: bool = false;
-
-#[inline(always)]
-fn open_database_ro() -> Result<Connection> {
-    if IN_MEMORY_DB {
-        Connection::open_in_memory_with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY)
-    } else {
-        Connection::open_with_flags(DB_PATH, OpenFlags::SQLITE_OPEN_READ_ONLY)
-    }
-}
-
-#[inline(always)]
-fn open_database_rw() -> Result<Connection> {
-    if IN_MEMORY_DB {
-        Connection::open_in_memory()
-    } else {
-        Connection::open(DB_PATH)
-    }
-}
-
-#[inline(always)]
-fn open_database(read_only: bool) -> Result<Connection> {
-    if read_only {
-        open_database_ro()
-    } else {
-        open_database_rw()
-    }
-}
-
-impl ReadOnlyConn {
-    #[inline(always)]
-    fn new() -> Result<ReadOnlyConn> {
-        Ok(ReadOnlyConn {
-            conn: open_database(true)?,
-        })
-    }
-}
-
-impl WriteableConn {
-    #[inline(always)]
-    fn new() -> Result<WriteableConn> {
-        Ok(WriteableConn {
-            conn: open_database(false)?,
-        })
-    }
+/// Returns the shared db handle, opening it on first use
+#[inline]
+pub fn get_db() -> Result<&'static Db> {
+    DB.get_or_try_init(|| Db::new(CONFIG.get_or_init(DbConfig::default).clone()))
 }
 
 #[inline]
 pub fn get_read_only_db() -> Result<impl ReadOnlyDb> {
-    ReadOnlyConn::new()
+    get_db()?.read_only()
 }
 
 #[inline]
 pub fn get_writeable_db() -> Result<impl WriteableDb> {
-    WriteableConn::new()
+    get_db()?.writeable()
 }
 
 #[inline]
 pub fn migrate() -> Result<()> {
-    migrations::migrate(&mut open_database(false)?)
+    get_db()?.migrate()
 }
 
 #[inline]
 pub fn writable_db_call<F, T>(f: F) -> Result<T>
 where
-    F: FnOnce(WriteableConn) -> Result<T>,
+    F: FnOnce(WriteableConn<'static>) -> Result<T>,
 {
-    f(WriteableConn::new()?)
+    f(get_db()?.writeable()?)
 }
 #[inline]
 pub fn read_only_db_call<F, T>(f: F) -> Result<T>
 where
-    F: FnOnce(ReadOnlyConn) -> Result<T>,
+    F: FnOnce(ReadOnlyConn<'static>) -> Result<T>,
 {
-    f(ReadOnlyConn::new()?)
+    f(get_db()?.read_only()?)
 }
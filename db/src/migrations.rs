@@ -166,6 +166,121 @@ migration![
     "DROP TABLE wordle;"
 ];
 
+migration![
+    11,
+    "CREATE TABLE server_settings (
+        server INTEGER NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (server, key),
+        FOREIGN KEY(server) REFERENCES server(id) ON DELETE CASCADE
+    );"
+];
+
+migration![
+    12,
+    "ALTER TABLE channel ADD COLUMN mode TEXT NOT NULL DEFAULT 'reply';"
+];
+
+migration![
+    13,
+    "ALTER TABLE user ADD COLUMN opted_out NUMERIC DEFAULT NULL;"
+];
+
+migration![
+    14,
+    "ALTER TABLE server ADD COLUMN left_at NUMERIC DEFAULT NULL;"
+];
+
+migration![
+    15,
+    "CREATE TABLE repost_event (
+        id INTEGER PRIMARY KEY,
+        message INTEGER NOT NULL,
+        original INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        link INTEGER DEFAULT NULL,
+        distance INTEGER DEFAULT NULL,
+        detected_at NUMERIC NOT NULL DEFAULT (datetime('now')),
+        FOREIGN KEY(message) REFERENCES message(id) ON DELETE CASCADE,
+        FOREIGN KEY(original) REFERENCES message(id) ON DELETE CASCADE,
+        FOREIGN KEY(link) REFERENCES link(id) ON DELETE CASCADE
+    );",
+    "CREATE UNIQUE INDEX idx_repost_event ON repost_event (message, original, kind, IFNULL(link, 0));",
+    "CREATE INDEX idx_repost_event_original ON repost_event (original);",
+    // backfill from what's already stored, only exact image matches can be found in sql
+    "INSERT OR IGNORE INTO repost_event (message, original, kind, link)
+    SELECT ML.message, MLO.message, 'link', ML.link
+    FROM message_link AS ML
+    JOIN message_link AS MLO ON MLO.link=ML.link
+    JOIN message AS M ON ML.message=M.id
+    JOIN message AS O ON MLO.message=O.id
+    WHERE O.server=M.server AND O.id < M.id;",
+    "INSERT OR IGNORE INTO repost_event (message, original, kind, distance)
+    SELECT MI.message, MIO.message, 'image', 0
+    FROM message_image AS MI
+    JOIN image AS I ON MI.image=I.id
+    JOIN image AS IO ON IO.hash=I.hash
+    JOIN message_image AS MIO ON MIO.image=IO.id
+    JOIN message AS M ON MI.message=M.id
+    JOIN message AS O ON MIO.message=O.id
+    WHERE O.server=M.server AND O.id < M.id;"
+];
+
+migration![
+    16,
+    "CREATE TABLE false_positive (
+        message INTEGER NOT NULL,
+        original INTEGER NOT NULL,
+        reported_by INTEGER NOT NULL,
+        reported_at NUMERIC NOT NULL DEFAULT (datetime('now')),
+        PRIMARY KEY (message, original),
+        FOREIGN KEY(message) REFERENCES message(id) ON DELETE CASCADE,
+        FOREIGN KEY(original) REFERENCES message(id) ON DELETE CASCADE
+    );"
+];
+
+migration![
+    17,
+    // threads are stored as channels, with the channel they were started in as the parent
+    "ALTER TABLE channel ADD COLUMN parent INTEGER DEFAULT NULL;"
+];
+
+migration![
+    18,
+    // how far back each channel's history has been loaded, cursor is the oldest message
+    // loaded so far and is NULL until the first page is loaded
+    "CREATE TABLE backfill (
+        channel INTEGER PRIMARY KEY,
+        cursor INTEGER DEFAULT NULL,
+        messages INTEGER NOT NULL DEFAULT 0,
+        priority INTEGER NOT NULL DEFAULT 0,
+        paused NUMERIC DEFAULT NULL,
+        done NUMERIC DEFAULT NULL,
+        started_at NUMERIC NOT NULL DEFAULT (datetime('now')),
+        updated_at NUMERIC DEFAULT NULL,
+        FOREIGN KEY(channel) REFERENCES channel(id) ON DELETE CASCADE
+    );"
+];
+
+migration![
+    19,
+    // full text search over links, the default tokenizer splits urls into the words in their
+    // host and path. Kept in sync with the link table by the triggers below.
+    "CREATE VIRTUAL TABLE link_search USING fts5(link, content='link', content_rowid='id');",
+    "CREATE TRIGGER link_search_insert AFTER INSERT ON link BEGIN
+        INSERT INTO link_search (rowid, link) VALUES (new.id, new.link);
+    END;",
+    "CREATE TRIGGER link_search_delete AFTER DELETE ON link BEGIN
+        INSERT INTO link_search (link_search, rowid, link) VALUES ('delete', old.id, old.link);
+    END;",
+    "CREATE TRIGGER link_search_update AFTER UPDATE ON link BEGIN
+        INSERT INTO link_search (link_search, rowid, link) VALUES ('delete', old.id, old.link);
+        INSERT INTO link_search (rowid, link) VALUES (new.id, new.link);
+    END;",
+    "INSERT INTO link_search (link_search) VALUES ('rebuild');"
+];
+
 fn delete_old_links(conn: &Connection) -> Result<()> {
     trace!("starting delete old links");
     conn.execute(
@@ -187,7 +302,7 @@ fn delete_old_links(conn: &Connection) -> Result<()> {
 pub(crate) fn migrate(conn: &mut Connection) -> Result<()> {
     const MIN_VER: u32 = 7;
     // be sure to increment this everytime a new migration is added
-    const FINAL_VER: u32 = 10;
+    const FINAL_VER: u32 = 19;
 
     let ver = queries::get_version(conn)?;
     info!("database version is currently: {ver} with target ver {FINAL_VER}");
@@ -219,6 +334,42 @@ pub(crate) fn migrate(conn: &mut Connection) -> Result<()> {
     if ver < 10 {
         migration_10(&tx)?;
     }
+
+    if ver < 11 {
+        migration_11(&tx)?;
+    }
+
+    if ver < 12 {
+        migration_12(&tx)?;
+    }
+
+    if ver < 13 {
+        migration_13(&tx)?;
+    }
+
+    if ver < 14 {
+        migration_14(&tx)?;
+    }
+
+    if ver < 15 {
+        migration_15(&tx)?;
+    }
+
+    if ver < 16 {
+        migration_16(&tx)?;
+    }
+
+    if ver < 17 {
+        migration_17(&tx)?;
+    }
+
+    if ver < 18 {
+        migration_18(&tx)?;
+    }
+
+    if ver < 19 {
+        migration_19(&tx)?;
+    }
     // delete old links we don't need
     delete_old_links(&tx)?;
 
@@ -305,13 +456,29 @@ mod tests {
     fn test_channel_table() -> Result<()> {
         let ti = get_table_info("channel")?.rows;
 
-        // Expect only 4 columns in channel table
-        assert_eq!(ti.len(), 4);
+        // Expect only 6 columns in channel table
+        assert_eq!(ti.len(), 6);
 
         assert!(ti.contains_key("id"));
         assert!(ti.contains_key("name"));
         assert!(ti.contains_key("visible"));
         assert!(ti.contains_key("server"));
+        assert!(ti.contains_key("mode"));
+        assert!(ti.contains_key("parent"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_channel_mode_column() -> Result<()> {
+        let table = get_table_info("channel")?;
+        table.assert_row("mode", "TEXT", 1, Some("'reply'"), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_channel_parent_column() -> Result<()> {
+        let table = get_table_info("channel")?;
+        table.assert_row("parent", "INTEGER", 0, Some("NULL"), 0);
         Ok(())
     }
 
@@ -325,6 +492,34 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_link_search_table() -> Result<()> {
+        let conn = get_migrated_db()?;
+        let search = |query: &str| -> Result<Vec<String>> {
+            conn.prepare("SELECT link FROM link_search WHERE link_search MATCH (?1);")?
+                .query_map([query], |row| row.get(0))?
+                .collect()
+        };
+
+        conn.execute(
+            "INSERT INTO link (link) VALUES ('https://www.bbc.com/news/article');",
+            [],
+        )?;
+        assert_eq!(
+            search("bbc news")?,
+            vec!["https://www.bbc.com/news/article"]
+        );
+        assert!(search("sport")?.is_empty());
+
+        conn.execute("UPDATE link SET link='https://www.bbc.com/sport';", [])?;
+        assert!(search("news")?.is_empty());
+        assert_eq!(search("sport")?.len(), 1);
+
+        conn.execute("DELETE FROM link;", [])?;
+        assert!(search("bbc")?.is_empty());
+        Ok(())
+    }
     #[test]
     fn test_message_table() -> Result<()> {
         let table = get_table_info("message")?;
@@ -369,13 +564,60 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_repost_event_table() -> Result<()> {
+        let table = get_table_info("repost_event")?;
+
+        assert_eq!(table.rows.len(), 7);
+        table.assert_row("id", "INTEGER", 0, None, 1);
+        table.assert_row("message", "INTEGER", 1, None, 0);
+        table.assert_row("original", "INTEGER", 1, None, 0);
+        table.assert_row("kind", "TEXT", 1, None, 0);
+        table.assert_row("link", "INTEGER", 0, Some("NULL"), 0);
+        table.assert_row("distance", "INTEGER", 0, Some("NULL"), 0);
+        table.assert_row("detected_at", "NUMERIC", 1, Some("datetime('now')"), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_false_positive_table() -> Result<()> {
+        let table = get_table_info("false_positive")?;
+
+        assert_eq!(table.rows.len(), 4);
+        table.assert_row("message", "INTEGER", 1, None, 1);
+        table.assert_row("original", "INTEGER", 1, None, 2);
+        table.assert_row("reported_by", "INTEGER", 1, None, 0);
+        table.assert_row("reported_at", "NUMERIC", 1, Some("datetime('now')"), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_backfill_table() -> Result<()> {
+        let table = get_table_info("backfill")?;
+
+        assert_eq!(table.rows.len(), 8);
+        table.assert_row("channel", "INTEGER", 0, None, 1);
+        table.assert_row("cursor", "INTEGER", 0, Some("NULL"), 0);
+        table.assert_row("messages", "INTEGER", 1, Some("0"), 0);
+        table.assert_row("priority", "INTEGER", 1, Some("0"), 0);
+        table.assert_row("paused", "NUMERIC", 0, Some("NULL"), 0);
+        table.assert_row("done", "NUMERIC", 0, Some("NULL"), 0);
+        table.assert_row("started_at", "NUMERIC", 1, Some("datetime('now')"), 0);
+        table.assert_row("updated_at", "NUMERIC", 0, Some("NULL"), 0);
+
+        Ok(())
+    }
+
     #[test]
     fn test_server_table() -> Result<()> {
         let table = get_table_info("server")?;
 
-        assert_eq!(table.rows.len(), 2);
+        assert_eq!(table.rows.len(), 3);
         table.assert_row("id", "INTEGER", 0, None, 1);
         table.assert_row("name", "TEXT", 0, None, 0);
+        table.assert_row("left_at", "NUMERIC", 0, Some("NULL"), 0);
 
         Ok(())
     }
@@ -384,11 +626,12 @@ mod tests {
     fn test_user_table() -> Result<()> {
         let table = get_table_info("user")?;
 
-        assert_eq!(table.rows.len(), 4);
+        assert_eq!(table.rows.len(), 5);
         table.assert_row("id", "INTEGER", 0, None, 1);
         table.assert_row("username", "TEXT", 1, None, 0);
         table.assert_row("bot", "BOOL", 1, None, 0);
         table.assert_row("discriminator", "INTEGER", 1, None, 0);
+        table.assert_row("opted_out", "NUMERIC", 0, Some("NULL"), 0);
 
         Ok(())
     }
@@ -421,4 +664,15 @@ mod tests {
         table.assert_row("replied_to", "INTEGER", 1, None, 0);
         Ok(())
     }
+
+    #[test]
+    fn test_server_settings_table() -> Result<()> {
+        let table = get_table_info("server_settings")?;
+
+        assert_eq!(table.rows.len(), 3);
+        table.assert_row("server", "INTEGER", 1, None, 1);
+        table.assert_row("key", "TEXT", 1, None, 2);
+        table.assert_row("value", "TEXT", 1, None, 0);
+        Ok(())
+    }
 }
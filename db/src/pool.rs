@@ -0,0 +1,260 @@
+use crate::config::DbConfig;
+use crate::connections::{GetConnectionImmutable, GetConnectionMutable};
+use crate::errors::Result;
+use crate::migrations;
+use crate::retry::RetryPolicy;
+use crate::{ReadOnlyDb, WriteableDb};
+
+use log::{debug, trace};
+use rusqlite::{Connection, OpenFlags};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+#[derive(Debug, Default)]
+struct Readers {
+    idle: Vec<Connection>,
+    // number of reader connections that exist, idle or checked out
+    open: usize,
+}
+
+/// Shared handle to the database. Holds a single writer connection and a bounded
+/// pool of reader connections that are reused across calls, so each call doesn't
+/// pay for opening a connection and re-preparing its statements.
+#[derive(Debug)]
+pub struct Db {
+    config: DbConfig,
+    writer: Mutex<Connection>,
+    readers: Mutex<Readers>,
+    reader_returned: Condvar,
+}
+
+impl Db {
+    pub fn new(config: DbConfig) -> Result<Db> {
+        // Opened up front, this also keeps an in memory db alive for the life of the pool
+        let writer = open_database(&config, false)?;
+        Ok(Db {
+            config,
+            writer: Mutex::new(writer),
+            readers: Mutex::new(Readers::default()),
+            reader_returned: Condvar::new(),
+        })
+    }
+
+    /// Checks out a reader connection, opening one if there are none idle and the pool
+    /// isn't full. Otherwise blocks until another reader is returned.
+    pub fn read_only(&self) -> Result<ReadOnlyConn<'_>> {
+        let mut readers = lock(&self.readers);
+        loop {
+            if let Some(conn) = readers.idle.pop() {
+                return Ok(ReadOnlyConn::new(self, conn));
+            }
+            if readers.open < self.config.max_readers.max(1) {
+                readers.open += 1;
+                drop(readers);
+                trace!("opening new reader connection");
+                return match open_database(&self.config, true) {
+                    Ok(conn) => Ok(ReadOnlyConn::new(self, conn)),
+                    Err(why) => {
+                        lock(&self.readers).open -= 1;
+                        self.reader_returned.notify_one();
+                        Err(why.into())
+                    }
+                };
+            }
+            readers = self
+                .reader_returned
+                .wait(readers)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Takes the writer connection, blocking until any other writer is finished with it
+    pub fn writeable(&self) -> Result<WriteableConn<'_>> {
+        Ok(WriteableConn {
+            conn: lock(&self.writer),
+            retry: RetryPolicy {
+                retries: self.config.write_retries,
+                backoff: self.config.retry_backoff,
+            },
+        })
+    }
+
+    pub fn migrate(&self) -> Result<()> {
+        Ok(migrations::migrate(&mut lock(&self.writer))?)
+    }
+
+    fn return_reader(&self, conn: Connection) {
+        lock(&self.readers).idle.push(conn);
+        self.reader_returned.notify_one();
+    }
+}
+
+// A panic whilst holding a connection doesn't leave the connection itself in a bad
+// state (any open transaction is rolled back on drop) so poisoning is ignored
+#[inline(always)]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub struct ReadOnlyConn<'a> {
+    db: &'a Db,
+    // only None after being returned to the pool on drop
+    conn: Option<Connection>,
+}
+
+impl ReadOnlyConn<'_> {
+    #[inline(always)]
+    fn new(db: &Db, conn: Connection) -> ReadOnlyConn<'_> {
+        ReadOnlyConn {
+            db,
+            conn: Some(conn),
+        }
+    }
+}
+
+impl Drop for ReadOnlyConn<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.db.return_reader(conn);
+        }
+    }
+}
+
+impl GetConnectionImmutable for ReadOnlyConn<'_> {
+    #[inline]
+    fn get_connection(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl ReadOnlyDb for ReadOnlyConn<'_> {}
+
+pub struct WriteableConn<'a> {
+    conn: MutexGuard<'a, Connection>,
+    retry: RetryPolicy,
+}
+
+impl GetConnectionImmutable for WriteableConn<'_> {
+    #[inline]
+    fn get_connection(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl GetConnectionMutable for WriteableConn<'_> {
+    #[inline]
+    fn get_mutable_connection(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    #[inline]
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+}
+
+impl ReadOnlyDb for WriteableConn<'_> {}
+
+impl WriteableDb for WriteableConn<'_> {}
+
+#[inline(always)]
+fn open_database_ro(config: &DbConfig) -> rusqlite::Result<Connection> {
+    if config.in_memory {
+        open_shared_memory(config, OpenFlags::SQLITE_OPEN_READ_ONLY)
+    } else {
+        Connection::open_with_flags(&config.path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+    }
+}
+
+#[inline(always)]
+fn open_database_rw(config: &DbConfig) -> rusqlite::Result<Connection> {
+    let conn = if config.in_memory {
+        open_shared_memory(
+            config,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+        )
+    } else {
+        Connection::open(&config.path)
+    }?;
+    conn.pragma_update_and_check(None, "journal_mode", config.journal_mode.as_str(), |_| {
+        Ok(())
+    })?;
+    Ok(conn)
+}
+
+// A plain in memory db is private to the connection that opened it, so use a
+// named shared cache db (keyed by the path) for every connection to see the same data
+#[inline(always)]
+fn open_shared_memory(config: &DbConfig, flags: OpenFlags) -> rusqlite::Result<Connection> {
+    Connection::open_with_flags(
+        format!("file:{}?mode=memory&cache=shared", config.path.display()),
+        flags | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_SHARED_CACHE,
+    )
+}
+
+fn open_database(config: &DbConfig, read_only: bool) -> rusqlite::Result<Connection> {
+    let conn = if read_only {
+        open_database_ro(config)
+    } else {
+        open_database_rw(config)
+    }?;
+    conn.busy_timeout(config.busy_timeout)?;
+    conn.pragma_update(None, "synchronous", config.synchronous.as_str())?;
+    // off by default in sqlite and set per connection, deletes rely on cascading
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+    conn.set_prepared_statement_cache_capacity(config.statement_cache_capacity);
+    debug!(
+        "opened {} connection to db",
+        if read_only { "read only" } else { "writeable" }
+    );
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::get_db;
+    use serenity::model::id::MessageId;
+
+    #[test]
+    fn test_foreign_keys_enabled() -> Result<()> {
+        let db = get_db("test_foreign_keys_enabled", 1)?;
+        // already migrated, so this returns before migrating anything
+        db.migrate()?;
+        let enabled = |conn: &Connection| -> Result<bool> {
+            Ok(conn.pragma_query_value(None, "foreign_keys", |row| row.get(0))?)
+        };
+        assert!(enabled(db.writeable()?.get_connection())?);
+        assert!(enabled(db.read_only()?.get_connection())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_readers_see_writes() -> Result<()> {
+        let db = get_db("test_readers_see_writes", 2)?;
+        {
+            let writer = db.writeable()?;
+            writer.update_server(1, &Some(String::from("server")))?;
+            writer.update_channel(2, 1, "channel", true)?;
+            writer.add_message(MessageId(3), 2, 1, 4)?;
+        }
+
+        assert!(db.read_only()?.get_message(MessageId(3))?.is_some());
+        assert_eq!(db.read_only()?.get_known_channels(1)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_readers_reused() -> Result<()> {
+        let db = get_db("test_readers_reused", 2)?;
+        {
+            let _r1 = db.read_only()?;
+            let _r2 = db.read_only()?;
+            assert_eq!(lock(&db.readers).open, 2);
+        }
+        let _r3 = db.read_only()?;
+        let readers = lock(&db.readers);
+        assert_eq!(readers.open, 2);
+        assert_eq!(readers.idle.len(), 1);
+        Ok(())
+    }
+}
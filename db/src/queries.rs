@@ -1,3 +1,4 @@
+use crate::errors;
 use crate::structs::Message;
 use rusqlite::{Connection, OptionalExtension, Result};
 
@@ -14,13 +15,14 @@ pub(crate) fn set_version(conn: &Connection, version: u32) -> Result<()> {
 }
 
 #[inline]
-pub(crate) fn get_message(conn: &Connection, msg_id: u64) -> Result<Option<Message>> {
-    conn.query_row(
-        "SELECT id, server, channel, author, created_at, 
+pub(crate) fn get_message(conn: &Connection, msg_id: u64) -> errors::Result<Option<Message>> {
+    Ok(conn
+        .prepare_cached(
+            "SELECT id, server, channel, author, created_at, 
         parsed_repost, deleted, checked_old, parsed_embed
         FROM message WHERE id=(?1)",
-        [msg_id],
-        |row| {
+        )?
+        .query_row([msg_id], |row| {
             Ok(Message::new(
                 row.get(0)?, // id
                 row.get(1)?, // server
@@ -32,7 +34,6 @@ pub(crate) fn get_message(conn: &Connection, msg_id: u64) -> Result<Option<Messa
                 row.get(6)?, // deleted
                 row.get(7)?, // checked_old
             ))
-        },
-    )
-    .optional()
+        })
+        .optional()?)
 }
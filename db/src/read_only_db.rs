@@ -1,9 +1,15 @@
 use crate::connections::GetConnectionImmutable;
+use crate::errors::Result;
 use crate::queries;
-use crate::structs::{Channel, Link, Message, Reply, RepostCount, ReposterCount};
+use crate::structs::{
+    Backfill, Channel, ChannelMode, ExportImage, ExportLink, ExportMessage, ExportRepost,
+    ExportUser, Link, Message, MessageFilter, Reply, RepostCount, ReposterCount, UserStats,
+};
 
-use rusqlite::{OptionalExtension, Result, Row};
+use chrono::{DateTime, Utc};
+use rusqlite::{OptionalExtension, Row};
 use serenity::model::id::{ChannelId, GuildId, MessageId};
+use std::time::Duration;
 
 #[inline(always)]
 fn extract_first_result<I, T>(iter: &mut I) -> Result<Option<T>>
@@ -19,47 +25,131 @@ where
     Ok(ret)
 }
 
+fn backfill_from_row(row: &Row<'_>) -> rusqlite::Result<Backfill> {
+    Ok(Backfill {
+        channel: row.get(0)?,
+        channel_name: row.get(1)?,
+        cursor: row.get(2)?,
+        messages: row.get(3)?,
+        paused: row.get(4)?,
+        done: row.get(5)?,
+    })
+}
+
+/// A Link from a row of L.id, L.link, S.id, C.id, M.id, M.created_at, C.name, S.name,
+/// M.author, M.parsed_repost, M.deleted, M.checked_old, M.parsed_embed
+fn link_from_row(row: &Row<'_>) -> rusqlite::Result<Link> {
+    Ok(Link {
+        id: row.get(0)?,
+        link: row.get(1)?,
+        channel_name: row.get(6)?,
+        server_name: row.get(7)?,
+        message: Message::new(
+            row.get(4)?,  // id
+            row.get(2)?,  // server
+            row.get(3)?,  // channel
+            row.get(8)?,  // author
+            row.get(5)?,  // created_at
+            row.get(9)?,  // parsed_repost
+            row.get(12)?, // parsed_embed
+            row.get(10)?, // deleted
+            row.get(11)?, // checked_old
+        ),
+    })
+}
+
+/// Turns search terms into an FTS5 query matching links with words starting with every
+/// term. Terms are split the same way the links are, so punctuation in them can't be read
+/// as query syntax. None if there's nothing to search for.
+fn link_search_query(terms: &str) -> Option<String> {
+    let words = terms
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{word}\"*"))
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+// limits the message M in channel C to a MessageFilter bound as ?2 to ?4
+macro_rules! message_filter {
+    () => {
+        "((?2) IS NULL OR M.created_at >= (?2))
+        AND ((?3) IS NULL OR M.created_at < (?3))
+        AND ((?4) IS NULL OR M.channel=(?4) OR C.parent=(?4))"
+    };
+}
+
+// conditions every export applies to the message M in channel C, with the server as ?1
+macro_rules! export_filter {
+    () => {
+        concat!(
+            "M.server=(?1)
+            AND C.visible=TRUE
+            AND M.deleted IS NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM user AS OU WHERE OU.id=M.author AND OU.opted_out IS NOT NULL
+            )
+            AND ",
+            message_filter!()
+        )
+    };
+}
+
+// every repost of a message the user ?2 posted in the server ?1 by someone else, with
+// when both were sent
+macro_rules! reposts_of_user {
+    () => {
+        "WITH reposted AS (
+            SELECT E.message, E.original, E.link, 
+                R.author AS reposter, R.created_at AS reposted_at, O.created_at AS posted_at
+            FROM repost_event AS E
+            JOIN message AS O ON E.original=O.id
+            JOIN message AS R ON E.message=R.id
+            JOIN channel AS C ON O.channel=C.id
+            WHERE O.server=(?1) AND 
+                O.author=(?2) AND 
+                R.author IS NOT O.author AND
+                C.visible=TRUE AND
+                O.deleted IS NULL AND
+                R.deleted IS NULL AND
+                NOT EXISTS (
+                    SELECT 1 FROM false_positive AS F 
+                    WHERE F.message=E.message AND F.original=E.original
+                )
+        ) "
+    };
+}
+
 pub trait ReadOnlyDb: GetConnectionImmutable {
     #[inline]
     fn get_message(&self, message_id: MessageId) -> Result<Option<Message>> {
         queries::get_message(self.get_connection(), *message_id.as_u64())
     }
 
+    /// The newest message stored for the channel that was sent before the given time,
+    /// catching up after downtime starts from it
     #[inline]
-    fn get_newest_unchecked_message(&self, server_id: u64) -> Result<Option<Message>> {
-        let mut stmt = self.get_connection().prepare(
-            "SELECT M.id, M.server, M.channel, M.author, M.created_at, 
-                    M.parsed_repost, M.deleted, M.checked_old, M.parsed_embed
-            FROM message as M 
-            JOIN channel as C on C.id=M.channel
-            WHERE 
-                C.visible=TRUE AND
-                M.deleted IS NULL AND
-                M.server=(?1) AND 
-                ( M.checked_old IS NULL OR
-                  M.parsed_embed IS NULL )
-            ORDER BY M.created_at desc
-            LIMIT 1",
-        )?;
-        let mut rows = stmt.query_map([server_id], |row| {
-            Ok(Message::new(
-                row.get(0)?, // id
-                row.get(1)?, // server
-                row.get(2)?, // channel
-                row.get(3)?, // author
-                row.get(4)?, // created_at
-                row.get(5)?, // parsed_repost
-                row.get(8)?, // parsed_embed
-                row.get(6)?, // deleted
-                row.get(7)?, // checked_old
-            ))
-        })?;
-        extract_first_result(&mut rows)
+    fn get_newest_message_in_channel(
+        &self,
+        channel_id: u64,
+        before: DateTime<Utc>,
+    ) -> Result<Option<u64>> {
+        Ok(self
+            .get_connection()
+            .prepare_cached(
+                "SELECT MAX(id) FROM message 
+                WHERE channel=(?1) AND created_at < (?2) AND deleted IS NULL",
+            )?
+            .query_row((channel_id, before), |row| row.get(0))?)
     }
 
     #[inline]
     fn get_known_channels(&self, server_id: u64) -> Result<Vec<Channel>> {
-        let mut stmt = self.get_connection().prepare(
+        let mut stmt = self.get_connection().prepare_cached(
             "SELECT * FROM channel 
             WHERE server=(?1) AND 
                 visible=TRUE",
@@ -83,9 +173,9 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
 
     #[inline]
     fn get_channel_list(&self, server_id: GuildId) -> Result<Vec<(ChannelId, String)>> {
-        let mut stmt = self
-            .get_connection()
-            .prepare("SELECT id, name FROM channel where server = (?1)")?;
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT id, name FROM channel where server = (?1) AND parent IS NULL",
+        )?;
         let rows = stmt.query_map([*server_id.as_u64()], |row| {
             Ok((ChannelId(row.get(0)?), row.get(1)?))
         })?;
@@ -96,10 +186,44 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
         Ok(links)
     }
 
+    /// Mode for the channel, channels we don't know about yet have the default mode
+    #[inline]
+    fn get_channel_mode(&self, channel_id: u64) -> Result<ChannelMode> {
+        Ok(self
+            .get_connection()
+            .prepare_cached("SELECT mode FROM channel WHERE id=(?1)")?
+            .query_row([channel_id], |row| row.get(0))
+            .optional()?
+            .unwrap_or_default())
+    }
+
     #[inline]
-    fn query_links(&self, link: &str, server: u64) -> Result<Vec<Link>> {
+    fn get_channel_modes(&self, server_id: u64) -> Result<Vec<(String, ChannelMode)>> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT name, mode FROM channel 
+            WHERE server=(?1) AND mode != 'reply' AND parent IS NULL
+            ORDER BY name",
+        )?;
+        let rows = stmt.query_map([server_id], |row| {
+            Ok((
+                row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                row.get(1)?,
+            ))
+        })?;
+
+        let mut modes = Vec::new();
+        for row in rows {
+            modes.push(row?)
+        }
+        Ok(modes)
+    }
+
+    /// Messages in the server with the link, excluding any marked as not being the
+    /// original for current_msg_id
+    #[inline]
+    fn query_links(&self, link: &str, server: u64, current_msg_id: u64) -> Result<Vec<Link>> {
         let conn = self.get_connection();
-        let mut stmt = conn.prepare(
+        let mut stmt = conn.prepare_cached(
             "SELECT 
                 L.id, L.link, S.id, C.id, M.id, M.created_at, C.name, 
                 S.name, M.author, M.parsed_repost, 
@@ -113,27 +237,58 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
                 L.link = (?1)
                 AND S.id = (?2)
                 AND C.visible = TRUE
-                AND M.deleted IS NULL;",
+                AND C.mode != 'off'
+                AND M.deleted IS NULL
+                AND NOT EXISTS (
+                    SELECT 1 FROM user AS U WHERE U.id=M.author AND U.opted_out IS NOT NULL
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM false_positive AS F 
+                    WHERE (F.message=(?3) AND F.original=M.id) OR 
+                        (F.message=M.id AND F.original=(?3))
+                );",
         )?;
-        let rows = stmt.query_map((link, server), |row| {
-            Ok(Link {
-                id: row.get(0)?,
-                link: row.get(1)?,
-                channel_name: row.get(6)?,
-                server_name: row.get(7)?,
-                message: Message::new(
-                    row.get(4)?,  // id
-                    row.get(2)?,  // server
-                    row.get(3)?,  // channel
-                    row.get(8)?,  // author
-                    row.get(5)?,  // created_at
-                    row.get(9)?,  // parsed_repost
-                    row.get(12)?, // parsed_embed
-                    row.get(10)?, // deleted
-                    row.get(11)?, // checked_old
-                ),
-            })
-        })?;
+        let rows = stmt.query_map((link, server, current_msg_id), link_from_row)?;
+
+        let mut links = Vec::new();
+        for row in rows {
+            links.push(row?)
+        }
+
+        Ok(links)
+    }
+
+    /// The most recent posts of links matching the search terms in the server, newest first
+    #[inline]
+    fn search_links(&self, server: u64, terms: &str, limit: u64) -> Result<Vec<Link>> {
+        let query = match link_search_query(terms) {
+            Some(query) => query,
+            None => return Ok(Vec::new()),
+        };
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT
+                L.id, L.link, S.id, C.id, M.id, M.created_at, C.name,
+                S.name, M.author, M.parsed_repost,
+                M.deleted, M.checked_old, M.parsed_embed
+            FROM link_search AS LS
+            JOIN link AS L ON L.id=LS.rowid
+            JOIN message_link AS ML ON ML.link=L.id
+            JOIN message AS M ON ML.message=M.id
+            JOIN channel AS C ON M.channel=C.id
+            JOIN server AS S ON M.server=S.id
+            WHERE
+                link_search MATCH (?1)
+                AND S.id = (?2)
+                AND C.visible = TRUE
+                AND C.mode != 'off'
+                AND M.deleted IS NULL
+                AND NOT EXISTS (
+                    SELECT 1 FROM user AS U WHERE U.id=M.author AND U.opted_out IS NOT NULL
+                )
+            ORDER BY M.created_at DESC, M.id DESC
+            LIMIT (?3);",
+        )?;
+        let rows = stmt.query_map((query, server, limit), link_from_row)?;
 
         let mut links = Vec::new();
         for row in rows {
@@ -143,10 +298,60 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
         Ok(links)
     }
 
+    /// The links stored for a message
+    #[inline]
+    fn get_message_links(&self, message_id: u64) -> Result<Vec<String>> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT L.link FROM message_link AS ML
+            JOIN link AS L ON ML.link=L.id
+            WHERE ML.message=(?1)",
+        )?;
+        let rows = stmt.query_map([message_id], |row| row.get(0))?;
+
+        let mut links = Vec::new();
+        for row in rows {
+            links.push(row?)
+        }
+        Ok(links)
+    }
+
+    /// The url and hash of every image stored for a message
+    #[inline]
+    fn get_message_images(&self, message_id: u64) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT I.url, I.hash FROM message_image AS MI
+            JOIN image AS I ON MI.image=I.id
+            WHERE MI.message=(?1)",
+        )?;
+        let rows = stmt.query_map([message_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut images = Vec::new();
+        for row in rows {
+            images.push(row?)
+        }
+        Ok(images)
+    }
+
+    /// Every image repost the message is part of, as (message, original)
+    #[inline]
+    fn get_image_reposts(&self, message_id: u64) -> Result<Vec<(u64, u64)>> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT message, original FROM repost_event
+            WHERE kind='image' AND (message=(?1) OR original=(?1))",
+        )?;
+        let rows = stmt.query_map([message_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut pairs = Vec::new();
+        for row in rows {
+            pairs.push(row?)
+        }
+        Ok(pairs)
+    }
+
     #[inline]
     fn query_reposts_for_message(&self, message_id: u64) -> Result<Vec<Message>> {
         let conn = self.get_connection();
-        let mut stmt = conn.prepare(
+        let mut stmt = conn.prepare_cached(
             "SELECT 
                 MR.id, MR.server, MR.channel, MR.author, MR.created_at, 
                 MR.parsed_repost, MR.deleted, MR.checked_old, MR.parsed_embed
@@ -156,9 +361,17 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
             JOIN server AS S ON M.server=S.id
             JOIN message_link AS MLR ON ML.link=MLR.link
             JOIN message AS MR ON MLR.message = MR.id
+            JOIN channel AS CR ON MR.channel=CR.id
             WHERE 
                 M.id = (?1)
                 AND C.visible = TRUE
+                AND CR.mode != 'off'
+                AND NOT EXISTS (
+                    SELECT 1 FROM user AS U WHERE U.id=MR.author AND U.opted_out IS NOT NULL
+                )
+                AND NOT EXISTS (
+                    SELECT 1 FROM false_positive AS F WHERE F.message=M.id AND F.original=MR.id
+                )
                 AND M.deleted IS NULL
                 AND M.server == MR.server
                 AND ML.id != MLR.id
@@ -193,7 +406,7 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
     ) -> Result<Vec<(Message, String)>> {
         let conn = self.get_connection();
 
-        let mut stmt = conn.prepare(
+        let mut stmt = conn.prepare_cached(
             "SELECT M.id, M.server, M.channel, M.author, M.created_at, 
             M.parsed_repost, M.deleted, M.checked_old, M.parsed_embed, I.hash
             FROM image as I
@@ -212,7 +425,16 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
             AND S.id = (?7)
             AND M.id != (?8)
             AND C.visible = TRUE
-            AND M.deleted IS NULL",
+            AND C.mode != 'off'
+            AND M.deleted IS NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM user AS U WHERE U.id=M.author AND U.opted_out IS NOT NULL
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM false_positive AS F 
+                WHERE (F.message=(?8) AND F.original=M.id) OR 
+                    (F.message=M.id AND F.original=(?8))
+            )",
         )?;
         assert!(hash.len() >= 5);
         let mut chars = hash.chars();
@@ -253,33 +475,55 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
         Ok(links)
     }
 
+    /// The most reposted links and images in the server, counting reposts sent within the
+    /// filter
     #[inline]
-    fn get_repost_list(&self, server_id: u64) -> Result<Vec<RepostCount>> {
+    fn get_repost_list(
+        &self,
+        server_id: u64,
+        filter: MessageFilter,
+        limit: u64,
+    ) -> Result<Vec<RepostCount>> {
         let conn = self.get_connection();
-        let mut stmt = conn.prepare(
-            "SELECT L.link, LM.link_count 
-            FROM link as L JOIN (
+        // link reposts are grouped by the link, image reposts by the earliest original
+        let mut stmt = conn.prepare_cached(concat!(
+            "SELECT L.link, O.server, O.channel, O.id, COUNT(1) + 1 as cnt
+            FROM (
                 SELECT 
-                    ML.link, 
-                    COUNT(1) as link_count,
-                    MAX(M.created_at) as most_recent 
-                FROM message_link as ML 
-                JOIN message as M on ML.message=M.id
+                    E.message, 
+                    E.link, 
+                    MIN(E.original) as original, 
+                    M.created_at
+                FROM repost_event as E
+                JOIN message as M on E.message=M.id
                 JOIN channel as C on M.channel=C.id
                 WHERE M.server=(?1) AND 
                     C.visible=TRUE AND
-                    M.deleted IS NULL
-                GROUP BY link
-                HAVING link_count > 1 
-            ) as LM on L.id=LM.link
-            ORDER BY link_count desc, most_recent desc
-            LIMIT 10",
-        )?;
+                    M.deleted IS NULL AND
+                    NOT EXISTS (
+                        SELECT 1 FROM false_positive AS F 
+                        WHERE F.message=E.message AND F.original=E.original
+                    ) AND ",
+            message_filter!(),
+            " GROUP BY E.message, E.kind, E.link
+            ) as R
+            JOIN message as O on R.original=O.id
+            LEFT JOIN link as L on R.link=L.id
+            GROUP BY R.link, CASE WHEN R.link IS NULL THEN R.original END
+            ORDER BY cnt desc, MAX(R.created_at) desc
+            LIMIT (?5)"
+        ))?;
 
-        let rows = stmt.query_map([server_id], |row| {
+        let params = (server_id, filter.since, filter.until, filter.channel, limit);
+        let rows = stmt.query_map(params, |row| {
+            let link: Option<String> = row.get(0)?;
             Ok(RepostCount {
-                link: row.get(0)?,
-                count: row.get(1)?,
+                link: match link {
+                    Some(link) => link,
+                    None => MessageId(row.get(3)?)
+                        .link(ChannelId(row.get(2)?), Some(GuildId(row.get(1)?))),
+                },
+                count: row.get(4)?,
             })
         })?;
 
@@ -291,30 +535,37 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
         Ok(reposts)
     }
 
+    /// Users with the most reposts sent within the filter
     #[inline]
-    fn get_top_reposters(&self, server_id: u64) -> Result<Vec<ReposterCount>> {
+    fn get_top_reposters(
+        &self,
+        server_id: u64,
+        filter: MessageFilter,
+        limit: u64,
+    ) -> Result<Vec<ReposterCount>> {
         let conn = self.get_connection();
-        let mut stmt = conn.prepare(
-            "SELECT U.username, COUNT(*) as cnt
-            FROM message_link AS L1
-            JOIN (
-                SELECT MIN(ML.id) as id, ML.link
-                FROM message_link as ML
-                JOIN message as M on M.id = ML.message 
-                JOIN channel as C on M.channel=C.id
-                WHERE M.server=(?1) AND 
-                    C.visible=TRUE AND
-                    M.deleted IS NULL
-                GROUP BY link
-            ) as L2 on L1.link=L2.link
-            JOIN message as M on M.id=L1.message
+        let mut stmt = conn.prepare_cached(concat!(
+            "SELECT U.username, COUNT(DISTINCT E.message) as cnt
+            FROM repost_event as E
+            JOIN message as M on E.message=M.id
+            JOIN channel as C on M.channel=C.id
             JOIN user as U on M.author=U.id
-            WHERE L1.id != L2.id
-            GROUP BY U.username
-            ORDER BY cnt desc",
-        )?;
+            WHERE M.server=(?1) AND 
+                C.visible=TRUE AND
+                M.deleted IS NULL AND
+                U.opted_out IS NULL AND
+                NOT EXISTS (
+                    SELECT 1 FROM false_positive AS F 
+                    WHERE F.message=E.message AND F.original=E.original
+                ) AND ",
+            message_filter!(),
+            " GROUP BY U.username
+            ORDER BY cnt desc
+            LIMIT (?5)"
+        ))?;
 
-        let rows = stmt.query_map([server_id], |row| {
+        let params = (server_id, filter.since, filter.until, filter.channel, limit);
+        let rows = stmt.query_map(params, |row| {
             Ok(ReposterCount {
                 username: row.get(0)?,
                 count: row.get(1)?,
@@ -329,21 +580,703 @@ pub trait ReadOnlyDb: GetConnectionImmutable {
         Ok(reposters)
     }
 
+    #[inline]
+    fn get_server_settings(&self, server_id: u64) -> Result<Vec<(String, String)>> {
+        let mut stmt = self
+            .get_connection()
+            .prepare_cached("SELECT key, value FROM server_settings WHERE server=(?1)")?;
+        let rows = stmt.query_map([server_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut settings = Vec::new();
+        for row in rows {
+            settings.push(row?)
+        }
+        Ok(settings)
+    }
+
+    /// Servers the bot was removed from at least `grace` ago
+    #[inline]
+    fn get_servers_left_for(&self, grace: Duration) -> Result<Vec<u64>> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT id FROM server 
+            WHERE left_at IS NOT NULL AND 
+                left_at <= datetime('now', (?1))",
+        )?;
+        let rows = stmt.query_map([format!("-{} seconds", grace.as_secs())], |row| row.get(0))?;
+
+        let mut servers = Vec::new();
+        for row in rows {
+            servers.push(row?)
+        }
+        Ok(servers)
+    }
+
+    #[inline]
+    fn is_opted_out(&self, user_id: u64) -> Result<bool> {
+        Ok(self
+            .get_connection()
+            .prepare_cached("SELECT opted_out IS NOT NULL FROM user WHERE id=(?1)")?
+            .query_row([user_id], |row| row.get(0))
+            .optional()?
+            .unwrap_or(false))
+    }
+
+    #[inline]
+    fn get_reply_by_id(&self, reply_id: u64) -> Result<Option<Reply>> {
+        let conn = self.get_connection();
+        Ok(conn
+            .prepare_cached(
+                "SELECT id, channel, replied_to
+            FROM reply WHERE id=(?1)",
+            )?
+            .query_row([reply_id], |row| {
+                Ok(Reply {
+                    id: row.get(0)?,
+                    channel: row.get(1)?,
+                    replied_to: row.get(2)?,
+                })
+            })
+            .optional()?)
+    }
+
+    /// The original messages for every repost found for the message, and the kind of repost
+    #[inline]
+    fn get_repost_originals(&self, message_id: u64) -> Result<Vec<(Message, String)>> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT O.id, O.server, O.channel, O.author, O.created_at, 
+                O.parsed_repost, O.deleted, O.checked_old, O.parsed_embed, E.kind
+            FROM repost_event AS E
+            JOIN message AS O ON E.original=O.id
+            JOIN channel AS C ON O.channel=C.id
+            WHERE 
+                E.message = (?1)
+                AND C.visible = TRUE
+                AND C.mode != 'off'
+                AND O.deleted IS NULL
+                AND NOT EXISTS (
+                    SELECT 1 FROM false_positive AS F 
+                    WHERE F.message=E.message AND F.original=E.original
+                )",
+        )?;
+        let rows = stmt.query_map([message_id], |row| {
+            Ok((
+                Message::new(
+                    row.get(0)?, // id
+                    row.get(1)?, // server
+                    row.get(2)?, // channel
+                    row.get(3)?, // author
+                    row.get(4)?, // created_at
+                    row.get(5)?, // parsed_repost
+                    row.get(8)?, // parsed_embed
+                    row.get(6)?, // deleted
+                    row.get(7)?, // checked_old
+                ),
+                row.get(9)?,
+            ))
+        })?;
+
+        let mut originals = Vec::new();
+        for row in rows {
+            originals.push(row?)
+        }
+        Ok(originals)
+    }
+
+    /// Our replies to messages that were found to be reposts of original_id
+    #[inline]
+    fn get_replies_citing(&self, original_id: u64) -> Result<Vec<Reply>> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT DISTINCT R.id, R.channel, R.replied_to
+            FROM repost_event AS E
+            JOIN reply AS R ON R.replied_to=E.message
+            WHERE E.original=(?1)",
+        )?;
+        let rows = stmt.query_map([original_id], |row| {
+            Ok(Reply {
+                id: row.get(0)?,
+                channel: row.get(1)?,
+                replied_to: row.get(2)?,
+            })
+        })?;
+
+        let mut replies = Vec::new();
+        for row in rows {
+            replies.push(row?)
+        }
+        Ok(replies)
+    }
+
+    /// The unfinished, unpaused backfill in the server that should load a page next.
+    /// Higher priorities go first, otherwise whichever was updated longest ago.
+    #[inline]
+    fn get_next_backfill(&self, server_id: u64) -> Result<Option<Backfill>> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT B.channel, C.name, B.cursor, B.messages, B.paused IS NOT NULL, B.done IS NOT NULL
+            FROM backfill AS B
+            JOIN channel AS C ON B.channel=C.id
+            WHERE C.server=(?1) AND C.visible = TRUE AND C.mode != 'off'
+                AND B.paused IS NULL AND B.done IS NULL
+            ORDER BY B.priority DESC, IFNULL(B.updated_at, B.started_at) ASC
+            LIMIT 1",
+        )?;
+        let mut rows = stmt.query_map([server_id], backfill_from_row)?;
+        extract_first_result(&mut rows)
+    }
+
+    #[inline]
+    fn get_backfills(&self, server_id: u64) -> Result<Vec<Backfill>> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "SELECT B.channel, C.name, B.cursor, B.messages, B.paused IS NOT NULL, B.done IS NOT NULL
+            FROM backfill AS B
+            JOIN channel AS C ON B.channel=C.id
+            WHERE C.server=(?1)
+            ORDER BY B.done IS NOT NULL, B.priority DESC, C.name",
+        )?;
+        let rows = stmt.query_map([server_id], backfill_from_row)?;
+
+        let mut backfills = Vec::new();
+        for row in rows {
+            backfills.push(row?)
+        }
+        Ok(backfills)
+    }
+
+    #[inline]
+    fn export_messages(&self, server_id: u64, filter: MessageFilter) -> Result<Vec<ExportMessage>> {
+        let mut stmt = self.get_connection().prepare_cached(concat!(
+            "SELECT M.id, M.channel, C.name, M.author, U.username, M.created_at
+            FROM message AS M
+            JOIN channel AS C ON M.channel=C.id
+            LEFT JOIN user AS U ON M.author=U.id
+            WHERE ",
+            export_filter!(),
+            " ORDER BY M.id"
+        ))?;
+        let params = (server_id, filter.since, filter.until, filter.channel);
+        let rows = stmt.query_map(params, |row| {
+            Ok(ExportMessage {
+                id: row.get(0)?,
+                channel: row.get(1)?,
+                channel_name: row.get(2)?,
+                author: row.get(3)?,
+                username: row.get(4)?,
+                created_at: row.get(5)?,
+            })
+        })?;
+
+        let mut messages = Vec::new();
+        for row in rows {
+            messages.push(row?)
+        }
+        Ok(messages)
+    }
+
+    #[inline]
+    fn export_links(&self, server_id: u64, filter: MessageFilter) -> Result<Vec<ExportLink>> {
+        let mut stmt = self.get_connection().prepare_cached(concat!(
+            "SELECT M.id, M.channel, M.author, M.created_at, L.link
+            FROM message_link AS ML
+            JOIN link AS L ON ML.link=L.id
+            JOIN message AS M ON ML.message=M.id
+            JOIN channel AS C ON M.channel=C.id
+            WHERE ",
+            export_filter!(),
+            " ORDER BY M.id"
+        ))?;
+        let params = (server_id, filter.since, filter.until, filter.channel);
+        let rows = stmt.query_map(params, |row| {
+            Ok(ExportLink {
+                message: row.get(0)?,
+                channel: row.get(1)?,
+                author: row.get(2)?,
+                created_at: row.get(3)?,
+                link: row.get(4)?,
+            })
+        })?;
+
+        let mut links = Vec::new();
+        for row in rows {
+            links.push(row?)
+        }
+        Ok(links)
+    }
+
+    #[inline]
+    fn export_images(&self, server_id: u64, filter: MessageFilter) -> Result<Vec<ExportImage>> {
+        let mut stmt = self.get_connection().prepare_cached(concat!(
+            "SELECT M.id, M.channel, M.author, M.created_at, I.url, I.hash
+            FROM message_image AS MI
+            JOIN image AS I ON MI.image=I.id
+            JOIN message AS M ON MI.message=M.id
+            JOIN channel AS C ON M.channel=C.id
+            WHERE ",
+            export_filter!(),
+            " ORDER BY M.id"
+        ))?;
+        let params = (server_id, filter.since, filter.until, filter.channel);
+        let rows = stmt.query_map(params, |row| {
+            Ok(ExportImage {
+                message: row.get(0)?,
+                channel: row.get(1)?,
+                author: row.get(2)?,
+                created_at: row.get(3)?,
+                url: row.get(4)?,
+                hash: row.get(5)?,
+            })
+        })?;
+
+        let mut images = Vec::new();
+        for row in rows {
+            images.push(row?)
+        }
+        Ok(images)
+    }
+
+    #[inline]
+    fn export_reposts(&self, server_id: u64, filter: MessageFilter) -> Result<Vec<ExportRepost>> {
+        let mut stmt = self.get_connection().prepare_cached(concat!(
+            "SELECT E.message, E.original, E.kind, L.link, E.distance, 
+                M.author, O.author, M.created_at
+            FROM repost_event AS E
+            JOIN message AS M ON E.message=M.id
+            JOIN message AS O ON E.original=O.id
+            JOIN channel AS C ON M.channel=C.id
+            LEFT JOIN link AS L ON E.link=L.id
+            WHERE O.deleted IS NULL AND
+                NOT EXISTS (
+                    SELECT 1 FROM false_positive AS F 
+                    WHERE F.message=E.message AND F.original=E.original
+                ) AND ",
+            export_filter!(),
+            " ORDER BY E.message, E.original"
+        ))?;
+        let params = (server_id, filter.since, filter.until, filter.channel);
+        let rows = stmt.query_map(params, |row| {
+            Ok(ExportRepost {
+                message: row.get(0)?,
+                original: row.get(1)?,
+                kind: row.get(2)?,
+                link: row.get(3)?,
+                distance: row.get(4)?,
+                author: row.get(5)?,
+                original_author: row.get(6)?,
+                created_at: row.get(7)?,
+            })
+        })?;
+
+        let mut reposts = Vec::new();
+        for row in rows {
+            reposts.push(row?)
+        }
+        Ok(reposts)
+    }
+
+    /// Message and repost counts for everyone that posted in the server
+    #[inline]
+    fn export_users(&self, server_id: u64, filter: MessageFilter) -> Result<Vec<ExportUser>> {
+        let mut stmt = self.get_connection().prepare_cached(concat!(
+            "SELECT U.id, U.username, COUNT(1) AS cnt,
+                SUM(EXISTS (
+                    SELECT 1 FROM repost_event AS E
+                    WHERE E.message=M.id AND NOT EXISTS (
+                        SELECT 1 FROM false_positive AS F 
+                        WHERE F.message=E.message AND F.original=E.original
+                    )
+                )),
+                SUM((
+                    SELECT COUNT(DISTINCT E.message) FROM repost_event AS E
+                    JOIN message AS R ON E.message=R.id
+                    WHERE E.original=M.id AND R.deleted IS NULL AND NOT EXISTS (
+                        SELECT 1 FROM false_positive AS F 
+                        WHERE F.message=E.message AND F.original=E.original
+                    )
+                ))
+            FROM message AS M
+            JOIN channel AS C ON M.channel=C.id
+            JOIN user AS U ON M.author=U.id
+            WHERE ",
+            export_filter!(),
+            " GROUP BY U.id ORDER BY cnt DESC, U.username"
+        ))?;
+        let params = (server_id, filter.since, filter.until, filter.channel);
+        let rows = stmt.query_map(params, |row| {
+            Ok(ExportUser {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                messages: row.get(2)?,
+                reposts: row.get(3)?,
+                reposted: row.get(4)?,
+            })
+        })?;
+
+        let mut users = Vec::new();
+        for row in rows {
+            users.push(row?)
+        }
+        Ok(users)
+    }
+
+    /// Stats for the user in the server, None if they opted out or aren't known at all
+    #[inline]
+    fn get_user_stats(&self, server_id: u64, user_id: u64) -> Result<Option<UserStats>> {
+        let conn = self.get_connection();
+        let username: Option<String> = conn
+            .prepare_cached("SELECT username FROM user WHERE id=(?1) AND opted_out IS NULL")?
+            .query_row([user_id], |row| row.get(0))
+            .optional()?;
+        let username = match username {
+            Some(username) => username,
+            None => return Ok(None),
+        };
+
+        let (links, images, reposts) = conn
+            .prepare_cached(
+                "WITH messages AS (
+                    SELECT M.id FROM message AS M
+                    JOIN channel AS C ON M.channel=C.id
+                    WHERE M.server=(?1) AND 
+                        M.author=(?2) AND
+                        C.visible=TRUE AND 
+                        M.deleted IS NULL
+                )
+                SELECT 
+                    (SELECT COUNT(1) FROM message_link 
+                    WHERE message IN (SELECT id FROM messages)),
+                    (SELECT COUNT(1) FROM message_image 
+                    WHERE message IN (SELECT id FROM messages)),
+                    (SELECT COUNT(DISTINCT E.message) FROM repost_event AS E
+                    WHERE E.message IN (SELECT id FROM messages) AND 
+                        NOT EXISTS (
+                            SELECT 1 FROM false_positive AS F 
+                            WHERE F.message=E.message AND F.original=E.original
+                        ))",
+            )?
+            .query_row((server_id, user_id), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
+
+        // the time to repost is from the earliest original when a repost has several
+        let (reposted, average_seconds) = conn
+            .prepare_cached(concat!(
+                reposts_of_user!(),
+                "SELECT 
+                    (SELECT COUNT(DISTINCT original) FROM reposted),
+                    (SELECT AVG(seconds) FROM (
+                        SELECT (julianday(reposted_at) - julianday(MIN(posted_at))) * 86400 
+                            AS seconds
+                        FROM reposted
+                        GROUP BY message
+                    ))"
+            ))?
+            .query_row((server_id, user_id), |row| {
+                Ok((row.get(0)?, row.get::<_, Option<f64>>(1)?))
+            })?;
+
+        let top_link = conn
+            .prepare_cached(concat!(
+                reposts_of_user!(),
+                "SELECT L.link, COUNT(DISTINCT R.message) AS cnt
+                FROM reposted AS R
+                JOIN link AS L ON R.link=L.id
+                GROUP BY L.id
+                ORDER BY cnt DESC, L.link
+                LIMIT 1"
+            ))?
+            .query_row((server_id, user_id), |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+
+        let top_reposter = conn
+            .prepare_cached(concat!(
+                reposts_of_user!(),
+                "SELECT U.username, COUNT(DISTINCT R.message) AS cnt
+                FROM reposted AS R
+                JOIN user AS U ON R.reposter=U.id
+                WHERE U.opted_out IS NULL
+                GROUP BY U.id
+                ORDER BY cnt DESC, U.username
+                LIMIT 1"
+            ))?
+            .query_row((server_id, user_id), |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?;
+
+        Ok(Some(UserStats {
+            username,
+            links,
+            images,
+            reposts,
+            reposted,
+            top_link,
+            average_repost_time: average_seconds
+                .map(|seconds| Duration::from_secs(seconds.max(0.0).round() as u64)),
+            top_reposter,
+        }))
+    }
+
     #[inline]
     fn get_reply(&self, replied_id: u64) -> Result<Option<Reply>> {
         let conn = self.get_connection();
-        conn.query_row(
-            "SELECT id, channel, replied_to
+        Ok(conn
+            .prepare_cached(
+                "SELECT id, channel, replied_to
             FROM reply WHERE replied_to=(?1)",
-            [replied_id],
-            |row| {
+            )?
+            .query_row([replied_id], |row| {
                 Ok(Reply {
                     id: row.get(0)?,
                     channel: row.get(1)?,
                     replied_to: row.get(2)?,
                 })
-            },
-        )
-        .optional()
+            })
+            .optional()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::seeded_db;
+    use crate::structs::RepostEvent;
+    use crate::WriteableDb;
+
+    #[test]
+    fn test_stats_from_repost_events() -> Result<()> {
+        let db = seeded_db("test_stats_from_repost_events")?;
+        let writer = db.writeable()?;
+        writer.add_user(5, "reposter", false, 1)?;
+        for (id, author) in [(10, 4), (11, 5), (12, 5), (13, 5)] {
+            writer.add_message(MessageId(id), 2, 1, author)?;
+        }
+        writer.execute(
+            "INSERT INTO link (id, link) VALUES (1, 'https://example.com')",
+            [],
+        )?;
+        writer.add_repost_events(&[
+            RepostEvent::link(11, 10, 1),
+            // out of order, 10 is still the original
+            RepostEvent::link(10, 12, 1),
+            RepostEvent::link(12, 11, 1),
+            RepostEvent::image(13, 10, 2),
+            // duplicates are ignored
+            RepostEvent::image(13, 10, 2),
+        ])?;
+
+        let reposts = writer.get_repost_list(1, MessageFilter::default(), 10)?;
+        assert_eq!(reposts.len(), 2);
+        assert_eq!(reposts[0].link, "https://example.com");
+        assert_eq!(reposts[0].count, 3);
+        assert_eq!(reposts[1].link, "https://discord.com/channels/1/2/10");
+        assert_eq!(reposts[1].count, 2);
+        let top = writer.get_repost_list(1, MessageFilter::default(), 1)?;
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].link, "https://example.com");
+
+        let reposters = writer.get_top_reposters(1, MessageFilter::default(), 10)?;
+        assert_eq!(reposters.len(), 1);
+        assert_eq!(reposters[0].username, "reposter");
+        assert_eq!(reposters[0].count, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_export() -> Result<()> {
+        let db = seeded_db("test_export")?;
+        let mut writer = db.writeable()?;
+        writer.update_thread(3, 1, "thread", 2, true)?;
+        writer.update_channel(5, 1, "memes", true)?;
+        writer.add_user(6, "reposter", false, 1)?;
+
+        // ids are snowflakes so the messages are sent on the given day
+        let day = |date: &str| -> (u64, DateTime<Utc>) {
+            let time = DateTime::parse_from_rfc3339(&format!("{date}T12:00:00Z"))
+                .unwrap()
+                .with_timezone(&Utc);
+            (
+                ((time.timestamp_millis() - 1420070400000) as u64) << 22,
+                time,
+            )
+        };
+        let (jan, feb, mar) = (
+            day("2023-01-01").0,
+            day("2023-02-01").0,
+            day("2023-03-01").0,
+        );
+        writer.add_message(MessageId(jan), 2, 1, 4)?;
+        writer.add_message(MessageId(feb), 3, 1, 6)?;
+        writer.add_message(MessageId(mar), 5, 1, 6)?;
+        for id in [jan, feb, mar] {
+            writer.insert_link("https://example.com", id)?;
+        }
+        let link_id = writer.query_links("https://example.com", 1, 0)?[0]
+            .id
+            .unwrap() as u64;
+        writer.add_repost_events(&[
+            RepostEvent::link(feb, jan, link_id),
+            RepostEvent::link(mar, jan, link_id),
+        ])?;
+
+        let everything = MessageFilter::default();
+        assert_eq!(writer.export_messages(1, everything)?.len(), 3);
+        assert_eq!(writer.export_links(1, everything)?.len(), 3);
+        let reposts = writer.export_reposts(1, everything)?;
+        assert_eq!(reposts.len(), 2);
+        assert_eq!(reposts[0].link.as_deref(), Some("https://example.com"));
+        assert_eq!(
+            (reposts[0].author, reposts[0].original_author),
+            (Some(6), Some(4))
+        );
+
+        let users = writer.export_users(1, everything)?;
+        assert_eq!(users[0].username, "reposter");
+        assert_eq!(
+            (users[0].messages, users[0].reposts, users[0].reposted),
+            (2, 2, 0)
+        );
+        assert_eq!(
+            (users[1].messages, users[1].reposts, users[1].reposted),
+            (1, 0, 2)
+        );
+
+        // the thread is included with its channel
+        let general = MessageFilter {
+            channel: Some(2),
+            ..MessageFilter::default()
+        };
+        let messages = writer.export_messages(1, general)?;
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].channel_name.as_deref(), Some("thread"));
+
+        let february = MessageFilter {
+            since: Some(day("2023-02-01").1),
+            until: Some(day("2023-03-01").1),
+            channel: None,
+        };
+        let reposts = writer.export_reposts(1, february)?;
+        assert_eq!(reposts.len(), 1);
+        assert_eq!(reposts[0].message, feb);
+        assert_eq!(writer.export_links(1, february)?.len(), 1);
+        assert!(writer.export_images(1, february)?.is_empty());
+
+        // leaderboards are filtered the same way
+        assert_eq!(writer.get_repost_list(1, everything, 10)?[0].count, 3);
+        assert_eq!(writer.get_repost_list(1, february, 10)?[0].count, 2);
+        assert_eq!(writer.get_top_reposters(1, general, 10)?[0].count, 1);
+        let memes = MessageFilter {
+            channel: Some(5),
+            ..february
+        };
+        assert!(writer.get_repost_list(1, memes, 10)?.is_empty());
+        assert!(writer.get_top_reposters(1, memes, 10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_stats() -> Result<()> {
+        let db = seeded_db("test_user_stats")?;
+        let mut writer = db.writeable()?;
+        writer.add_user(5, "reposter", false, 1)?;
+        writer.add_user(6, "other", false, 1)?;
+
+        // an hour and two hours after the original
+        let hour = (3600 * 1000) << 22;
+        let (original, first, second) = (1 << 32, (1 << 32) + hour, (1 << 32) + 2 * hour);
+        writer.add_message(MessageId(original), 2, 1, 4)?;
+        writer.add_message(MessageId(first), 2, 1, 5)?;
+        writer.add_message(MessageId(second), 2, 1, 5)?;
+        for id in [original, first, second] {
+            writer.insert_link("https://example.com", id)?;
+        }
+        writer.insert_link("https://example.org", original)?;
+        let link_id = writer.query_links("https://example.com", 1, 0)?[0]
+            .id
+            .unwrap() as u64;
+        writer.add_repost_events(&[
+            RepostEvent::link(first, original, link_id),
+            RepostEvent::link(second, original, link_id),
+            RepostEvent::link(second, first, link_id),
+        ])?;
+
+        let stats = writer.get_user_stats(1, 4)?.unwrap();
+        assert_eq!(stats.username, "poster");
+        assert_eq!((stats.links, stats.images, stats.reposts), (2, 0, 0));
+        assert_eq!(stats.reposted, 1);
+        assert_eq!(
+            stats.top_link,
+            Some((String::from("https://example.com"), 2))
+        );
+        assert_eq!(
+            stats.average_repost_time,
+            Some(Duration::from_secs(90 * 60))
+        );
+        assert_eq!(stats.top_reposter, Some((String::from("reposter"), 2)));
+
+        // reposting yourself doesn't count
+        let stats = writer.get_user_stats(1, 5)?.unwrap();
+        assert_eq!((stats.links, stats.reposts, stats.reposted), (2, 2, 0));
+        assert!(stats.top_reposter.is_none());
+
+        let stats = writer.get_user_stats(1, 6)?.unwrap();
+        assert_eq!((stats.links, stats.reposts), (0, 0));
+        assert!(stats.average_repost_time.is_none());
+        assert!(writer.get_user_stats(1, 7)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_links() -> Result<()> {
+        let db = seeded_db("test_search_links")?;
+        let mut writer = db.writeable()?;
+        writer.update_channel(3, 1, "hidden", false)?;
+
+        let (older, newer, hidden) = (1 << 32, 2 << 32, 3 << 32);
+        writer.add_message(MessageId(older), 2, 1, 4)?;
+        writer.add_message(MessageId(newer), 2, 1, 4)?;
+        writer.add_message(MessageId(hidden), 3, 1, 4)?;
+        writer.insert_link("https://www.bbc.com/news/article-1", older)?;
+        writer.insert_link("https://www.bbc.com/news/article-1", newer)?;
+        writer.insert_link("https://www.bbc.com/sport", newer)?;
+        writer.insert_link("https://www.bbc.com/news/article-2", hidden)?;
+
+        let ids = |terms: &str, limit: u64| -> Result<Vec<(u64, String)>> {
+            Ok(writer
+                .search_links(1, terms, limit)?
+                .into_iter()
+                .map(|link| (link.message.id, link.link))
+                .collect())
+        };
+        // newest first, words match by prefix and punctuation is ignored
+        assert_eq!(
+            ids("bbc.com/news", 10)?,
+            vec![
+                (newer, String::from("https://www.bbc.com/news/article-1")),
+                (older, String::from("https://www.bbc.com/news/article-1")),
+            ]
+        );
+        assert_eq!(ids("spo", 10)?.len(), 1);
+        assert_eq!(ids("bbc", 2)?.len(), 2);
+        assert_eq!(ids("\"news", 10)?.len(), 2);
+        assert!(ids("guardian", 10)?.is_empty());
+        assert!(ids("::", 10)?.is_empty());
+        assert!(writer.search_links(5, "bbc", 10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_newest_message_in_channel() -> Result<()> {
+        let db = seeded_db("test_newest_message_in_channel")?;
+        let writer = db.writeable()?;
+        writer.update_channel(3, 1, "empty", true)?;
+        for id in [1 << 32, 3 << 32, 2 << 32] {
+            writer.add_message(MessageId(id), 2, 1, 4)?;
+        }
+        let now = Utc::now();
+        assert_eq!(writer.get_newest_message_in_channel(2, now)?, Some(3 << 32));
+        assert_eq!(writer.get_newest_message_in_channel(3, now)?, None);
+        // messages from after the bot started came in live
+        let started = writer.get_message(MessageId(3 << 32))?.unwrap().created_at;
+        assert_eq!(
+            writer.get_newest_message_in_channel(2, started)?,
+            Some(2 << 32)
+        );
+        Ok(())
     }
 }
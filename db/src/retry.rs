@@ -0,0 +1,110 @@
+use crate::errors::{Error, Result};
+
+use log::warn;
+use rusqlite::ErrorCode;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Copy, Clone)]
+pub struct RetryPolicy {
+    /// number of retries after the first attempt
+    pub retries: u32,
+    /// wait before the first retry, doubled on every retry after
+    pub backoff: Duration,
+}
+
+/// Returns true if the error is sqlite telling us another connection holds a lock
+#[inline]
+fn is_busy(why: &rusqlite::Error) -> bool {
+    matches!(
+        why,
+        rusqlite::Error::SqliteFailure(err, _)
+            if err.code == ErrorCode::DatabaseBusy || err.code == ErrorCode::DatabaseLocked
+    )
+}
+
+/// Runs f, running it again with exponential backoff for as long as it fails
+/// because the db is busy or locked. f must be safe to run more than once, i.e.
+/// a transaction that is rolled back on failure.
+pub(crate) fn with_retry<T, F>(policy: RetryPolicy, mut f: F) -> Result<T>
+where
+    F: FnMut() -> rusqlite::Result<T>,
+{
+    let mut backoff = policy.backoff;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(ret) => return Ok(ret),
+            Err(why) if is_busy(&why) => {
+                if attempt > policy.retries {
+                    return Err(Error::RetriesExhausted {
+                        attempts: attempt,
+                        last: why,
+                    });
+                }
+                warn!("db busy on attempt {attempt}, retrying in {backoff:?}: {why}");
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(why) => return Err(why.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::ffi;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        retries: 3,
+        backoff: Duration::ZERO,
+    };
+
+    fn busy() -> rusqlite::Error {
+        rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_BUSY), None)
+    }
+
+    #[test]
+    fn test_retry_until_success() {
+        let mut calls = 0;
+        let ret = with_retry(POLICY, || {
+            calls += 1;
+            if calls < 3 {
+                Err(busy())
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(ret.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_retries_exhausted() {
+        let mut calls = 0;
+        let ret: Result<()> = with_retry(POLICY, || {
+            calls += 1;
+            Err(busy())
+        });
+        assert!(matches!(
+            ret,
+            Err(Error::RetriesExhausted { attempts: 4, .. })
+        ));
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn test_no_retry_other_errors() {
+        let mut calls = 0;
+        let ret: Result<()> = with_retry(POLICY, || {
+            calls += 1;
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        });
+        assert!(matches!(
+            ret,
+            Err(Error::Rusqlite(rusqlite::Error::QueryReturnedNoRows))
+        ));
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,81 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use std::fmt;
+use std::str::FromStr;
+
+/// How the bot behaves in a channel
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+    /// messages aren't checked and don't count as originals for other messages
+    Off,
+    /// messages are recorded and checked but reposts are never called out
+    Silent,
+    /// reposts get a reply linking to the originals
+    #[default]
+    Reply,
+    /// reposts get a reaction rather than a reply
+    React,
+}
+
+impl ChannelMode {
+    pub const ALL: [ChannelMode; 4] = [
+        ChannelMode::Off,
+        ChannelMode::Silent,
+        ChannelMode::Reply,
+        ChannelMode::React,
+    ];
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            ChannelMode::Off => "off",
+            ChannelMode::Silent => "silent",
+            ChannelMode::Reply => "reply",
+            ChannelMode::React => "react",
+        }
+    }
+}
+
+impl fmt::Display for ChannelMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ChannelMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ChannelMode::ALL
+            .into_iter()
+            .find(|mode| mode.as_str().eq_ignore_ascii_case(s))
+            .ok_or(())
+    }
+}
+
+impl ToSql for ChannelMode {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for ChannelMode {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|_| FromSqlError::InvalidType)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        for mode in ChannelMode::ALL {
+            assert_eq!(mode.as_str().parse(), Ok(mode));
+        }
+        assert_eq!("Silent".parse(), Ok(ChannelMode::Silent));
+        assert_eq!("loud".parse::<ChannelMode>(), Err(()));
+    }
+}
@@ -0,0 +1,54 @@
+use chrono::{DateTime, Utc};
+
+#[derive(Debug)]
+pub struct ExportMessage {
+    pub id: u64,
+    pub channel: u64,
+    pub channel_name: Option<String>,
+    pub author: Option<u64>,
+    pub username: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct ExportLink {
+    pub message: u64,
+    pub channel: u64,
+    pub author: Option<u64>,
+    pub created_at: DateTime<Utc>,
+    pub link: String,
+}
+
+#[derive(Debug)]
+pub struct ExportImage {
+    pub message: u64,
+    pub channel: u64,
+    pub author: Option<u64>,
+    pub created_at: DateTime<Utc>,
+    pub url: Option<String>,
+    pub hash: String,
+}
+
+/// A repost event, filtered by when the repost (not the original) was sent
+#[derive(Debug)]
+pub struct ExportRepost {
+    pub message: u64,
+    pub original: u64,
+    pub kind: String,
+    pub link: Option<String>,
+    pub distance: Option<u32>,
+    pub author: Option<u64>,
+    pub original_author: Option<u64>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+pub struct ExportUser {
+    pub id: u64,
+    pub username: String,
+    pub messages: u64,
+    /// messages by the user that were reposts
+    pub reposts: u64,
+    /// times the user's messages were reposted by anyone
+    pub reposted: u64,
+}
@@ -84,14 +84,13 @@ impl Message {
     pub const fn is_checked_old(&self) -> bool {
         self.checked_old.is_some()
     }
-    /// Returns true if message is less than 15s old
+    /// Returns true if message is less than threshold seconds old
     #[inline]
-    pub fn is_recent(&self) -> bool {
-        const RECENT_THRESHOLD: i64 = 15;
+    pub fn is_recent(&self, threshold: i64) -> bool {
         let seconds = Utc::now()
             .signed_duration_since(self.created_at)
             .num_seconds();
-        seconds < RECENT_THRESHOLD
+        seconds < threshold
     }
 
     #[inline]
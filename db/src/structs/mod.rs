@@ -1,10 +1,17 @@
+mod channel_mode;
+mod export;
 mod link;
 mod message;
 
+pub use channel_mode::ChannelMode;
+pub use export::{ExportImage, ExportLink, ExportMessage, ExportRepost, ExportUser};
 pub use link::Channel;
 pub use link::Link;
 pub use message::Message;
 
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
 #[derive(Debug)]
 pub struct Reply {
     pub id: u64,
@@ -12,6 +19,67 @@ pub struct Reply {
     pub replied_to: u64,
 }
 
+/// A message found to be a repost of an older message
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RepostEvent {
+    pub message: u64,
+    pub original: u64,
+    pub kind: &'static str,
+    pub link: Option<u64>,
+    pub distance: Option<u32>,
+}
+
+impl RepostEvent {
+    // messages can be processed out of order when catching up on old messages, so
+    // whichever of the two is older is the original
+    #[inline]
+    fn new(
+        message: u64,
+        other: u64,
+        kind: &'static str,
+        link: Option<u64>,
+        distance: Option<u32>,
+    ) -> RepostEvent {
+        RepostEvent {
+            message: message.max(other),
+            original: message.min(other),
+            kind,
+            link,
+            distance,
+        }
+    }
+
+    pub fn link(message: u64, other: u64, link: u64) -> RepostEvent {
+        RepostEvent::new(message, other, "link", Some(link), None)
+    }
+
+    pub fn image(message: u64, other: u64, distance: u32) -> RepostEvent {
+        RepostEvent::new(message, other, "image", None, Some(distance))
+    }
+}
+
+/// How far back a channel's history has been loaded
+#[derive(Debug)]
+pub struct Backfill {
+    pub channel: u64,
+    pub channel_name: Option<String>,
+    /// oldest message loaded so far, the next page is loaded from before it
+    pub cursor: Option<u64>,
+    pub messages: u64,
+    pub paused: bool,
+    pub done: bool,
+}
+
+/// Limits a query to messages sent in a time range and/or in a channel, threads are
+/// included with the channel they're in
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MessageFilter {
+    pub since: Option<DateTime<Utc>>,
+    /// exclusive
+    pub until: Option<DateTime<Utc>>,
+    pub channel: Option<u64>,
+}
+
 #[derive(Debug, Default)]
 pub struct RepostCount {
     pub link: String,
@@ -23,3 +91,21 @@ pub struct ReposterCount {
     pub username: String,
     pub count: u64,
 }
+
+/// What a user has posted in a server and how much of it has been reposted
+#[derive(Debug, Default)]
+pub struct UserStats {
+    pub username: String,
+    pub links: u64,
+    pub images: u64,
+    /// messages by the user that were reposts
+    pub reposts: u64,
+    /// messages by the user that someone else reposted
+    pub reposted: u64,
+    /// the user's link that was reposted the most, and how many times
+    pub top_link: Option<(String, u64)>,
+    /// average time between the user posting something and someone else reposting it
+    pub average_repost_time: Option<Duration>,
+    /// whoever reposted the user the most, and how many times
+    pub top_reposter: Option<(String, u64)>,
+}
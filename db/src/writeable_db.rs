@@ -1,16 +1,34 @@
 use crate::connections::GetConnectionMutable;
+use crate::errors::Result;
 use crate::queries;
-use crate::structs::Message;
+use crate::retry::with_retry;
+use crate::structs::{ChannelMode, Message, RepostEvent};
 use crate::ReadOnlyDb;
 
 use log::{debug, info, warn};
-use rusqlite::{Error, Result};
+use rusqlite::{Error, Transaction, TransactionBehavior};
 use serenity::model::id::{ChannelId, MessageId};
 
+// Deletes the messages selected by `messages`, a query for message ids taking a single
+// parameter. Everything referencing them is deleted along with them by the foreign keys.
+fn delete_messages(tx: &Transaction<'_>, messages: &str, param: u64) -> rusqlite::Result<()> {
+    tx.prepare_cached(&format!("DELETE FROM message WHERE id IN ({messages})"))?
+        .execute([param])?;
+    Ok(())
+}
+
+fn delete_unused_links_and_images(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.prepare_cached("DELETE FROM link WHERE id NOT IN (SELECT link FROM message_link)")?
+        .execute([])?;
+    tx.prepare_cached("DELETE FROM image WHERE id NOT IN (SELECT image FROM message_image)")?
+        .execute([])?;
+    Ok(())
+}
+
 pub trait WriteableDb: GetConnectionMutable + ReadOnlyDb {
     #[inline]
     fn update_server(&self, server_id: u64, name: &Option<String>) -> Result<()> {
-        let mut stmt = self.get_connection().prepare(
+        let mut stmt = self.get_connection().prepare_cached(
             "INSERT INTO server (id, name) VALUES ( ?1, ?2 )
             ON CONFLICT(id) DO UPDATE SET name=excluded.name
             WHERE (server.name IS NULL AND excluded.name IS NOT NULL)",
@@ -44,7 +62,7 @@ pub trait WriteableDb: GetConnectionMutable + ReadOnlyDb {
         author_id: u64,
     ) -> Result<Message> {
         let conn = self.get_connection();
-        let mut stmt = conn.prepare(
+        let mut stmt = conn.prepare_cached(
             "INSERT INTO message (id, server, channel, created_at, author) 
             VALUES ( ?1, ?2, ?3, ?4, ?5 )
             ON CONFLICT(id) DO UPDATE SET author=excluded.author
@@ -65,14 +83,14 @@ pub trait WriteableDb: GetConnectionMutable + ReadOnlyDb {
             None => {
                 // should return a special error at some point
                 warn!("No message with input id found despite being just added");
-                Err(Error::QueryReturnedNoRows)
+                Err(Error::QueryReturnedNoRows.into())
             }
         }
     }
 
     #[inline]
     fn add_user(&self, user_id: u64, username: &str, bot: bool, discriminator: u16) -> Result<()> {
-        let mut stmt = self.get_connection().prepare(
+        let mut stmt = self.get_connection().prepare_cached(
             "INSERT INTO user (id, username, bot, discriminator) 
             VALUES ( ?1, ?2, ?3, ?4 )
             ON CONFLICT(id) DO UPDATE SET 
@@ -91,9 +109,84 @@ pub trait WriteableDb: GetConnectionMutable + ReadOnlyDb {
         Ok(())
     }
 
+    /// Marks the user as opted out and deletes every message we have stored for them
+    #[inline]
+    fn opt_out_user(&mut self, user_id: u64) -> Result<()> {
+        info!("Opting out user {user_id} and purging their messages");
+
+        let policy = self.retry_policy();
+        let conn = self.get_mutable_connection();
+        with_retry(policy, || {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            tx.prepare_cached("UPDATE user SET opted_out=datetime('now') WHERE id=(?1)")?
+                .execute([user_id])?;
+            delete_messages(&tx, "SELECT id FROM message WHERE author=(?1)", user_id)?;
+            delete_unused_links_and_images(&tx)?;
+            tx.commit()
+        })
+    }
+
+    /// Deletes everything stored about a user, including the user itself
+    #[inline]
+    fn erase_user(&mut self, user_id: u64) -> Result<()> {
+        info!("Erasing all data for user {user_id}");
+
+        let policy = self.retry_policy();
+        let conn = self.get_mutable_connection();
+        with_retry(policy, || {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            delete_messages(&tx, "SELECT id FROM message WHERE author=(?1)", user_id)?;
+            tx.prepare_cached("DELETE FROM nickname WHERE user=(?1)")?
+                .execute([user_id])?;
+            tx.prepare_cached("DELETE FROM user WHERE id=(?1)")?
+                .execute([user_id])?;
+            delete_unused_links_and_images(&tx)?;
+            tx.commit()
+        })
+    }
+
+    /// Deletes everything stored about a server, including the server itself. Users
+    /// that have nothing left stored for them are deleted too.
+    #[inline]
+    fn erase_server(&mut self, server_id: u64) -> Result<()> {
+        info!("Erasing all data for server {server_id}");
+
+        let policy = self.retry_policy();
+        let conn = self.get_mutable_connection();
+        with_retry(policy, || {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            delete_messages(&tx, "SELECT id FROM message WHERE server=(?1)", server_id)?;
+            for sql in [
+                "DELETE FROM reply WHERE channel IN (SELECT id FROM channel WHERE server=(?1))",
+                "DELETE FROM nickname WHERE server=(?1)",
+                "DELETE FROM server_settings WHERE server=(?1)",
+                "DELETE FROM backfill WHERE channel IN (SELECT id FROM channel WHERE server=(?1))",
+                "DELETE FROM channel WHERE server=(?1)",
+                "DELETE FROM server WHERE id=(?1)",
+            ] {
+                tx.prepare_cached(sql)?.execute([server_id])?;
+            }
+            // keep opted out users so they stay opted out
+            tx.prepare_cached(
+                "DELETE FROM user 
+                WHERE opted_out IS NULL AND
+                    id NOT IN (SELECT author FROM message WHERE author IS NOT NULL) AND
+                    id NOT IN (SELECT user FROM nickname)",
+            )?
+            .execute([])?;
+            delete_unused_links_and_images(&tx)?;
+            tx.commit()
+        })
+    }
+
+    #[inline]
+    fn opt_in_user(&self, user_id: u64) -> Result<()> {
+        self.execute("UPDATE user SET opted_out=NULL WHERE id=(?1)", [user_id])
+    }
+
     #[inline]
     fn add_nickname(&self, user_id: u64, server_id: u64, nickname: &str) -> Result<()> {
-        let mut stmt = self.get_connection().prepare(
+        let mut stmt = self.get_connection().prepare_cached(
             "INSERT OR IGNORE INTO nickname (user, server, nickname) 
             VALUES ( ?1, ?2, ?3 )",
         )?;
@@ -118,33 +211,14 @@ pub trait WriteableDb: GetConnectionMutable + ReadOnlyDb {
     }
 
     #[inline]
-    fn mark_message_checked_old(&self, message_id: MessageId) -> Result<()> {
-        self.execute(
-            "UPDATE message 
-            SET checked_old=datetime('now')
-            WHERE id=(?1)",
-            [*message_id.as_u64()],
-        )
-    }
-
-    #[inline]
-    fn delete_message(&self, message_id: MessageId) -> Result<()> {
-        self.execute("DELETE FROM message WHERE id=(?1)", [*message_id.as_u64()])
-    }
-
-    // Soft delete is for when we query for a message, but get no result,
-    // this can occur if a message was deleted whilst the bot was down.
-    //
-    // If we see a message deleted we should (and do) just delete the message
-    // outright from the DB. Soft delete is for this other case as we aren't
-    // entirely sure what we should do with this. For safety not deleting and
-    // and just filtering from relevent queries.
-    #[inline]
-    fn soft_delete_message(&self, message_id: u64) -> Result<()> {
-        self.execute(
-            "UPDATE message SET deleted=datetime('now') WHERE id=(?1)",
-            [message_id],
-        )
+    fn delete_message(&mut self, message_id: MessageId) -> Result<()> {
+        let policy = self.retry_policy();
+        let conn = self.get_mutable_connection();
+        with_retry(policy, || {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            delete_messages(&tx, "SELECT (?1)", *message_id.as_u64())?;
+            tx.commit()
+        })
     }
 
     #[inline]
@@ -155,7 +229,7 @@ pub trait WriteableDb: GetConnectionMutable + ReadOnlyDb {
         name: &str,
         visible: bool,
     ) -> Result<()> {
-        let mut stmt = self.get_connection().prepare(
+        let mut stmt = self.get_connection().prepare_cached(
             "INSERT INTO channel (id, name, server, visible) VALUES ( ?1, ?2, ?3, ?4 )
             ON CONFLICT(id) DO UPDATE SET 
                 name=excluded.name,
@@ -177,22 +251,88 @@ pub trait WriteableDb: GetConnectionMutable + ReadOnlyDb {
 
                 Ok(())
             }
-            Err(why) => Err(why),
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    /// Adds or updates a thread, new threads start with the same mode as their parent
+    #[inline]
+    fn update_thread(
+        &self,
+        thread_id: u64,
+        server_id: u64,
+        name: &str,
+        parent_id: u64,
+        visible: bool,
+    ) -> Result<()> {
+        let cnt = self
+            .get_connection()
+            .prepare_cached(
+                "INSERT INTO channel (id, name, server, visible, parent, mode) 
+                VALUES ( ?1, ?2, ?3, ?4, ?5, IFNULL((SELECT mode FROM channel WHERE id=(?5)), 'reply') )
+                ON CONFLICT(id) DO UPDATE SET 
+                    name=excluded.name,
+                    visible=excluded.visible,
+                    parent=excluded.parent
+                WHERE (
+                    channel.name != excluded.name OR
+                    channel.visible != excluded.visible OR
+                    channel.parent IS NOT excluded.parent
+                )",
+            )?
+            .execute((thread_id, name, server_id, visible, parent_id))?;
+        if cnt > 0 {
+            debug!(
+                "Added/updated thread_id {thread_id} with name {name} in channel {parent_id} to db"
+            );
         }
+        Ok(())
     }
 
+    /// Threads in the channel get the same visibility as it
     #[inline]
     fn update_channel_visibility(&self, channel_id: ChannelId, visible: bool) -> Result<()> {
         self.execute(
-            "UPDATE channel SET visible = (?1) WHERE id = (?2)",
+            "UPDATE channel SET visible = (?1) WHERE id = (?2) OR parent = (?2)",
             (visible, *channel_id.as_u64()),
         )
     }
 
+    #[inline]
+    fn mark_server_left(&self, server_id: u64) -> Result<()> {
+        self.execute(
+            "UPDATE server SET left_at=datetime('now') WHERE id=(?1)",
+            [server_id],
+        )
+    }
+
+    #[inline]
+    fn mark_server_joined(&self, server_id: u64) -> Result<()> {
+        self.execute(
+            "UPDATE server SET left_at=NULL WHERE id=(?1) AND left_at IS NOT NULL",
+            [server_id],
+        )
+    }
+
+    /// Sets the mode for the channel and any threads in it
+    #[inline]
+    fn set_channel_mode(&self, channel_id: u64, mode: ChannelMode) -> Result<()> {
+        self.execute(
+            "UPDATE channel SET mode = (?1) WHERE id = (?2) OR parent = (?2)",
+            (mode, channel_id),
+        )
+    }
+
+    /// Deletes the channel and any threads in it, along with everything posted in them
     #[inline]
     fn delete_channel(&self, channel_id: ChannelId) -> Result<()> {
         self.execute(
-            "DELETE FROM channel WHERE id = (?1)",
+            "DELETE FROM backfill 
+            WHERE channel IN (SELECT id FROM channel WHERE id = (?1) OR parent = (?1))",
+            [*channel_id.as_u64()],
+        )?;
+        self.execute(
+            "DELETE FROM channel WHERE id = (?1) OR parent = (?1)",
             [*channel_id.as_u64()],
         )
     }
@@ -201,67 +341,248 @@ pub trait WriteableDb: GetConnectionMutable + ReadOnlyDb {
     fn insert_link(&mut self, link: &str, message_id: u64) -> Result<()> {
         debug!("Inserting the following link {:?}", link);
 
+        let policy = self.retry_policy();
         let conn = self.get_mutable_connection();
-        let tx = conn.transaction()?;
-        tx.execute(
-            "INSERT INTO link (link) VALUES (?1) ON CONFLICT(link) DO NOTHING;",
-            [link],
-        )?;
-        tx.execute(
-            "INSERT INTO message_link (link, message) 
-            VALUES (
-                (SELECT id FROM link WHERE link=(?1)), 
-                ?2
-            );",
-            (link, message_id),
-        )?;
+        with_retry(policy, || {
+            // take the write lock up front so the busy timeout applies, a deferred
+            // transaction can fail outright when upgrading from a read to a write
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            tx.prepare_cached("INSERT INTO link (link) VALUES (?1) ON CONFLICT(link) DO NOTHING;")?
+                .execute([link])?;
+            tx.prepare_cached(
+                "INSERT INTO message_link (link, message) 
+                VALUES (
+                    (SELECT id FROM link WHERE link=(?1)), 
+                    ?2
+                );",
+            )?
+            .execute((link, message_id))?;
+
+            tx.commit()
+        })
+    }
 
-        tx.commit()?;
+    /// Removes links from a message, i.e. after they were edited out of it. Reposts found
+    /// through the removed links, in either direction, are removed too.
+    #[inline]
+    fn delete_message_links(&mut self, message_id: u64, links: &[String]) -> Result<()> {
+        debug!("Removing links {links:?} from message {message_id}");
 
-        Ok(())
+        let policy = self.retry_policy();
+        let conn = self.get_mutable_connection();
+        with_retry(policy, || {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            for link in links {
+                tx.prepare_cached(
+                    "DELETE FROM repost_event 
+                    WHERE kind='link' 
+                        AND (message=(?1) OR original=(?1))
+                        AND link=(SELECT id FROM link WHERE link=(?2))",
+                )?
+                .execute((message_id, link))?;
+                tx.prepare_cached(
+                    "DELETE FROM message_link 
+                    WHERE message=(?1) AND link=(SELECT id FROM link WHERE link=(?2))",
+                )?
+                .execute((message_id, link))?;
+            }
+            delete_unused_links_and_images(&tx)?;
+            tx.commit()
+        })
     }
 
     #[inline]
     fn insert_image(&mut self, url: &str, hash: &str, message_id: u64) -> Result<()> {
         debug!("Inserting the following image hash {:?}", hash);
 
-        let tx = self.get_mutable_connection().transaction()?;
         let mut chars = hash.chars();
+        let (c1, c2, c3, c4, c5) = (
+            String::from(chars.next().unwrap()),
+            String::from(chars.nth(1).unwrap()),
+            String::from(chars.nth(2).unwrap()),
+            String::from(chars.nth(3).unwrap()),
+            String::from(chars.nth(4).unwrap()),
+        );
+
+        let policy = self.retry_policy();
+        let conn = self.get_mutable_connection();
+        with_retry(policy, || {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            tx.prepare_cached(
+                "INSERT INTO image (c1, c2, c3, c4, c5, hash, url) 
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT(url) DO NOTHING;",
+            )?
+            .execute((&c1, &c2, &c3, &c4, &c5, hash, url))?;
+
+            tx.prepare_cached(
+                "INSERT INTO message_image (image, message)
+                VALUES (
+                    (SELECT id FROM image WHERE url=(?1)),
+                    ?2
+                );",
+            )?
+            .execute((url, message_id))?;
+
+            tx.commit()
+        })
+    }
+
+    /// Removes images from a message, i.e. after an attachment was removed or embeds were
+    /// suppressed. Images no other message uses are removed too.
+    #[inline]
+    fn delete_message_images(&mut self, message_id: u64, urls: &[String]) -> Result<()> {
+        debug!("Removing images {urls:?} from message {message_id}");
+
+        let policy = self.retry_policy();
+        let conn = self.get_mutable_connection();
+        with_retry(policy, || {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            for url in urls {
+                tx.prepare_cached(
+                    "DELETE FROM message_image 
+                    WHERE message=(?1) AND image=(SELECT id FROM image WHERE url=(?2))",
+                )?
+                .execute((message_id, url))?;
+            }
+            delete_unused_links_and_images(&tx)?;
+            tx.commit()
+        })
+    }
 
-        tx.execute(
-            "INSERT INTO image (c1, c2, c3, c4, c5, hash, url) 
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-            ON CONFLICT(url) DO NOTHING;",
-            (
-                String::from(chars.next().unwrap()),
-                String::from(chars.nth(1).unwrap()),
-                String::from(chars.nth(2).unwrap()),
-                String::from(chars.nth(3).unwrap()),
-                String::from(chars.nth(4).unwrap()),
-                hash,
-                url,
-            ),
+    #[inline]
+    fn add_repost_events(&self, events: &[RepostEvent]) -> Result<()> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "INSERT OR IGNORE INTO repost_event (message, original, kind, link, distance) 
+            VALUES ( ?1, ?2, ?3, ?4, ?5 )",
         )?;
+        for event in events {
+            stmt.execute((
+                event.message,
+                event.original,
+                event.kind,
+                event.link,
+                event.distance,
+            ))?;
+        }
+        Ok(())
+    }
 
-        tx.execute(
-            "INSERT INTO message_image (image, message)
-            VALUES (
-                (SELECT id FROM image WHERE url=(?1)),
-                ?2
-            );",
-            (url, message_id),
+    #[inline]
+    fn delete_repost_events(&self, events: &[RepostEvent]) -> Result<()> {
+        let mut stmt = self.get_connection().prepare_cached(
+            "DELETE FROM repost_event 
+            WHERE message=(?1) AND original=(?2) AND kind=(?3) 
+                AND IFNULL(link, 0)=IFNULL((?4), 0)",
         )?;
+        for event in events {
+            stmt.execute((event.message, event.original, event.kind, event.link))?;
+        }
+        Ok(())
+    }
 
-        tx.commit()?;
+    /// Marks every repost found for the message as not actually being a repost
+    #[inline]
+    fn add_false_positives(&self, message_id: u64, reported_by: u64) -> Result<()> {
+        self.execute(
+            "INSERT OR IGNORE INTO false_positive (message, original, reported_by)
+            SELECT message, original, (?2) FROM repost_event WHERE message=(?1)",
+            (message_id, reported_by),
+        )
+    }
 
-        Ok(())
+    #[inline]
+    fn set_server_setting(&self, server_id: u64, key: &str, value: &str) -> Result<()> {
+        self.execute(
+            "INSERT INTO server_settings (server, key, value) VALUES ( ?1, ?2, ?3 )
+            ON CONFLICT(server, key) DO UPDATE SET value=excluded.value",
+            (server_id, key, value),
+        )
+    }
+
+    #[inline]
+    fn reset_server_setting(&self, server_id: u64, key: &str) -> Result<()> {
+        self.execute(
+            "DELETE FROM server_settings WHERE server=(?1) AND key=(?2)",
+            (server_id, key),
+        )
+    }
+
+    #[inline]
+    fn delete_reply(&self, reply_id: u64) -> Result<()> {
+        self.execute("DELETE FROM reply WHERE id=(?1)", [reply_id])
+    }
+
+    /// Starts loading the channel's history, or resumes it if it was paused. A finished
+    /// backfill is left alone.
+    #[inline]
+    fn start_backfill(&self, channel_id: u64) -> Result<()> {
+        self.execute(
+            "INSERT INTO backfill (channel) VALUES (?1)
+            ON CONFLICT(channel) DO UPDATE SET paused=NULL",
+            [channel_id],
+        )
+    }
+
+    /// Starts or resumes loading history for every visible channel in the server
+    #[inline]
+    fn start_server_backfill(&self, server_id: u64) -> Result<()> {
+        self.execute(
+            "INSERT INTO backfill (channel)
+            SELECT id FROM channel WHERE server=(?1) AND visible = TRUE AND mode != 'off'
+            ON CONFLICT(channel) DO UPDATE SET paused=NULL",
+            [server_id],
+        )
+    }
+
+    #[inline]
+    fn pause_backfill(&self, channel_id: u64) -> Result<()> {
+        self.execute(
+            "UPDATE backfill SET paused=datetime('now') WHERE channel=(?1) AND paused IS NULL",
+            [channel_id],
+        )
+    }
+
+    #[inline]
+    fn pause_server_backfill(&self, server_id: u64) -> Result<()> {
+        self.execute(
+            "UPDATE backfill SET paused=datetime('now') 
+            WHERE channel IN (SELECT id FROM channel WHERE server=(?1)) AND paused IS NULL",
+            [server_id],
+        )
+    }
+
+    /// Moves the channel ahead of every other backfill
+    #[inline]
+    fn prioritise_backfill(&self, channel_id: u64) -> Result<()> {
+        self.execute(
+            "UPDATE backfill SET priority=(SELECT MAX(priority) + 1 FROM backfill) 
+            WHERE channel=(?1)",
+            [channel_id],
+        )
+    }
+
+    /// Records a page of history being loaded, with cursor being the oldest message in
+    /// it. A page with no cursor means there's nothing older left and the backfill is done.
+    #[inline]
+    fn advance_backfill(&self, channel_id: u64, cursor: Option<u64>, messages: u64) -> Result<()> {
+        self.execute(
+            "UPDATE backfill SET 
+                cursor=IFNULL((?2), cursor),
+                messages=messages + (?3),
+                done=CASE WHEN (?2) IS NULL THEN datetime('now') ELSE NULL END,
+                updated_at=datetime('now')
+            WHERE channel=(?1)",
+            (channel_id, cursor, messages),
+        )
     }
 
     #[inline]
     fn add_reply(&self, message_id: u64, channel_id: u64, replied_id: u64) -> Result<()> {
-        let mut stmt = self.get_connection().prepare(
+        // replies to messages that weren't stored, like opted out users' commands, are skipped
+        let mut stmt = self.get_connection().prepare_cached(
             "INSERT INTO reply (id, channel, replied_to) 
-            VALUES ( ?1, ?2, ?3 )
+            SELECT ?1, ?2, id FROM message WHERE id=(?3)
             ON CONFLICT(id) DO NOTHING",
         )?;
 
@@ -269,3 +590,327 @@ pub trait WriteableDb: GetConnectionMutable + ReadOnlyDb {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::GetConnectionImmutable;
+    use crate::fixtures::seeded_db;
+    use crate::structs::MessageFilter;
+    use serenity::model::id::GuildId;
+    use std::time::Duration;
+
+    #[test]
+    fn test_opt_out_purges_messages() -> Result<()> {
+        let db = seeded_db("test_opt_out_purges_messages")?;
+        let mut writer = db.writeable()?;
+        writer.add_message(MessageId(3), 2, 1, 4)?;
+        writer.insert_link("https://example.com", 3)?;
+        assert_eq!(writer.query_links("https://example.com", 1, 0)?.len(), 1);
+
+        writer.opt_out_user(4)?;
+        assert!(writer.is_opted_out(4)?);
+        assert!(writer.get_message(MessageId(3))?.is_none());
+        assert!(writer.query_links("https://example.com", 1, 0)?.is_empty());
+
+        writer.opt_in_user(4)?;
+        assert!(!writer.is_opted_out(4)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_erase_user() -> Result<()> {
+        let db = seeded_db("test_erase_user")?;
+        let mut writer = db.writeable()?;
+        writer.add_user(5, "other", false, 1)?;
+        writer.add_nickname(4, 1, "nick")?;
+        writer.add_message(MessageId(3), 2, 1, 4)?;
+        writer.add_message(MessageId(6), 2, 1, 5)?;
+        writer.insert_link("https://example.com", 3)?;
+        writer.insert_link("https://example.org", 6)?;
+
+        writer.erase_user(4)?;
+        assert!(writer.get_message(MessageId(3))?.is_none());
+        assert!(writer.get_message(MessageId(6))?.is_some());
+        assert!(writer.query_links("https://example.org", 1, 0)?.len() == 1);
+        let count = |table: &str| -> Result<u64> {
+            Ok(writer.get_connection().query_row(
+                &format!("SELECT COUNT(*) FROM {table}"),
+                [],
+                |row| row.get(0),
+            )?)
+        };
+        assert_eq!(count("user")?, 1);
+        assert_eq!(count("nickname")?, 0);
+        assert_eq!(count("link")?, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_erase_server() -> Result<()> {
+        let db = seeded_db("test_erase_server")?;
+        let mut writer = db.writeable()?;
+        writer.update_server(11, &None)?;
+        writer.update_channel(12, 11, "channel", true)?;
+        writer.add_user(14, "user", false, 1)?;
+        for server in [1, 11] {
+            writer.add_message(MessageId(server + 2), server + 1, server, server + 3)?;
+            writer.insert_link("https://example.com", server + 2)?;
+        }
+        writer.set_server_setting(1, "image_distance", "3")?;
+        writer.add_reply(100, 2, 3)?;
+
+        writer.erase_server(1)?;
+        assert!(writer.get_message(MessageId(3))?.is_none());
+        assert!(writer.get_server_settings(1)?.is_empty());
+        assert!(writer.get_known_channels(1)?.is_empty());
+        assert!(writer.get_reply(3)?.is_none());
+        assert_eq!(writer.query_links("https://example.com", 11, 0)?.len(), 1);
+        let users: u64 =
+            writer
+                .get_connection()
+                .query_row("SELECT COUNT(*) FROM user", [], |row| row.get(0))?;
+        assert_eq!(users, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_servers_left() -> Result<()> {
+        let db = seeded_db("test_servers_left")?;
+        let writer = db.writeable()?;
+        writer.mark_server_left(1)?;
+        assert_eq!(writer.get_servers_left_for(Duration::ZERO)?, vec![1]);
+        assert!(writer
+            .get_servers_left_for(Duration::from_secs(60))?
+            .is_empty());
+
+        writer.mark_server_joined(1)?;
+        assert!(writer.get_servers_left_for(Duration::ZERO)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_false_positives_excluded() -> Result<()> {
+        let db = seeded_db("test_false_positives_excluded")?;
+        let mut writer = db.writeable()?;
+        for id in [10, 11] {
+            writer.add_message(MessageId(id), 2, 1, 4)?;
+            writer.insert_link("https://example.com", id)?;
+        }
+        let link_id = writer.query_links("https://example.com", 1, 0)?[0]
+            .id
+            .unwrap() as u64;
+        writer.add_repost_events(&[RepostEvent::link(11, 10, link_id)])?;
+        assert_eq!(
+            writer
+                .get_repost_list(1, MessageFilter::default(), 10)?
+                .len(),
+            1
+        );
+
+        writer.add_false_positives(11, 5)?;
+        assert_eq!(writer.query_links("https://example.com", 1, 11)?.len(), 1);
+        assert_eq!(writer.query_links("https://example.com", 1, 10)?.len(), 1);
+        assert!(writer.query_reposts_for_message(11)?.is_empty());
+        assert!(writer
+            .get_repost_list(1, MessageFilter::default(), 10)?
+            .is_empty());
+        assert!(writer
+            .get_top_reposters(1, MessageFilter::default(), 10)?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_channel() -> Result<()> {
+        let db = seeded_db("test_delete_channel")?;
+        let mut writer = db.writeable()?;
+        writer.update_thread(3, 1, "thread", 2, true)?;
+        writer.update_channel(5, 1, "other", true)?;
+        for (id, channel) in [(10, 2), (11, 3), (12, 5)] {
+            writer.add_message(MessageId(id), channel, 1, 4)?;
+            writer.insert_link("https://example.com", id)?;
+            writer.insert_image("https://a.com/1.png", "abcdefghijklmnop", id)?;
+        }
+        let link_id = writer.query_links("https://example.com", 1, 0)?[0]
+            .id
+            .unwrap() as u64;
+        writer.add_repost_events(&[RepostEvent::link(12, 10, link_id)])?;
+        writer.add_reply(100, 2, 10)?;
+        writer.start_backfill(3)?;
+
+        // everything in the channel and its threads goes with it
+        writer.delete_channel(ChannelId(2))?;
+        assert!(writer.get_message(MessageId(10))?.is_none());
+        assert!(writer.get_message(MessageId(11))?.is_none());
+        assert!(writer.get_message_links(10)?.is_empty());
+        assert!(writer.get_message_images(11)?.is_empty());
+        assert!(writer.get_repost_originals(12)?.is_empty());
+        assert!(writer.get_reply(10)?.is_none());
+        assert!(writer.get_backfills(1)?.is_empty());
+        // but not anything elsewhere in the server
+        assert!(writer.get_message(MessageId(12))?.is_some());
+        assert_eq!(writer.query_links("https://example.com", 1, 0)?.len(), 1);
+        assert_eq!(writer.hash_matches("abcdefghijklmnop", 1, 0)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_parents() -> Result<()> {
+        let db = seeded_db("test_missing_parents")?;
+        let writer = db.writeable()?;
+        // messages need their channel stored first
+        assert!(writer.add_message(MessageId(10), 3, 1, 4).is_err());
+        // replies to messages that were never stored are skipped
+        writer.add_reply(100, 2, 10)?;
+        assert!(writer.get_reply(10)?.is_none());
+        writer.add_message(MessageId(10), 2, 1, 4)?;
+        writer.add_reply(100, 2, 10)?;
+        assert!(writer.get_reply(10)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_original() -> Result<()> {
+        let db = seeded_db("test_delete_original")?;
+        let mut writer = db.writeable()?;
+        for id in [10, 11, 12] {
+            writer.add_message(MessageId(id), 2, 1, 4)?;
+        }
+        writer
+            .add_repost_events(&[RepostEvent::image(12, 10, 0), RepostEvent::image(12, 11, 1)])?;
+        writer.add_reply(100, 2, 12)?;
+
+        let replies = writer.get_replies_citing(10)?;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].replied_to, 12);
+        assert_eq!(writer.get_repost_originals(12)?.len(), 2);
+
+        writer.delete_message(MessageId(10))?;
+        let originals = writer.get_repost_originals(12)?;
+        assert_eq!(originals.len(), 1);
+        assert_eq!(originals[0].0.id, 11);
+        assert_eq!(originals[0].1, "image");
+        assert!(writer.get_replies_citing(10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_message_links() -> Result<()> {
+        let db = seeded_db("test_delete_message_links")?;
+        let mut writer = db.writeable()?;
+        for id in [10, 11] {
+            writer.add_message(MessageId(id), 2, 1, 4)?;
+        }
+        writer.insert_link("https://a.com", 10)?;
+        writer.insert_link("https://b.com", 10)?;
+        writer.insert_link("https://a.com", 11)?;
+        let link_id = writer.query_links("https://a.com", 1, 0)?[0].id.unwrap() as u64;
+        writer.add_repost_events(&[RepostEvent::link(11, 10, link_id)])?;
+        assert_eq!(writer.get_repost_originals(11)?.len(), 1);
+
+        writer.delete_message_links(10, &[String::from("https://a.com")])?;
+        assert_eq!(writer.get_message_links(10)?, vec!["https://b.com"]);
+        assert_eq!(writer.get_message_links(11)?, vec!["https://a.com"]);
+        assert!(writer.get_repost_originals(11)?.is_empty());
+
+        writer.delete_message_links(10, &[String::from("https://b.com")])?;
+        assert!(writer.get_message_links(10)?.is_empty());
+        assert!(writer.query_links("https://b.com", 1, 0)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_message_images() -> Result<()> {
+        let db = seeded_db("test_delete_message_images")?;
+        let mut writer = db.writeable()?;
+        for id in [10, 11] {
+            writer.add_message(MessageId(id), 2, 1, 4)?;
+        }
+        writer.insert_image("https://a.com/1.png", "abcdefghijklmnop", 10)?;
+        writer.insert_image("https://a.com/2.png", "qrstuvwxyzabcdef", 10)?;
+        writer.insert_image("https://a.com/1.png", "abcdefghijklmnop", 11)?;
+        writer.add_repost_events(&[RepostEvent::image(11, 10, 0)])?;
+        assert_eq!(writer.get_image_reposts(10)?, vec![(11, 10)]);
+
+        writer.delete_message_images(10, &[String::from("https://a.com/2.png")])?;
+        assert_eq!(
+            writer.get_message_images(10)?,
+            vec![(
+                String::from("https://a.com/1.png"),
+                String::from("abcdefghijklmnop")
+            )]
+        );
+        assert!(writer.hash_matches("qrstuvwxyzabcdef", 1, 0)?.is_empty());
+
+        writer.delete_message_images(10, &[String::from("https://a.com/1.png")])?;
+        writer.delete_repost_events(&[RepostEvent::image(11, 10, 0)])?;
+        assert!(writer.get_message_images(10)?.is_empty());
+        assert_eq!(writer.get_message_images(11)?.len(), 1);
+        assert!(writer.get_image_reposts(10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_threads() -> Result<()> {
+        let db = seeded_db("test_threads")?;
+        let writer = db.writeable()?;
+        writer.set_channel_mode(2, ChannelMode::Silent)?;
+        writer.update_thread(3, 1, "thread", 2, true)?;
+        writer.update_thread(4, 1, "other thread", 2, true)?;
+
+        // threads start with their parent's mode and follow changes to it
+        assert_eq!(writer.get_channel_mode(3)?, ChannelMode::Silent);
+        writer.set_channel_mode(2, ChannelMode::React)?;
+        assert_eq!(writer.get_channel_mode(4)?, ChannelMode::React);
+        // but are left out of the channel list
+        assert_eq!(writer.get_channel_list(GuildId(1))?.len(), 1);
+        assert!(writer
+            .get_channel_modes(1)?
+            .iter()
+            .all(|(name, _)| name == "channel"));
+
+        writer.update_channel_visibility(ChannelId(2), false)?;
+        assert!(writer.get_known_channels(1)?.is_empty());
+        writer.update_channel_visibility(ChannelId(2), true)?;
+        assert_eq!(writer.get_known_channels(1)?.len(), 3);
+
+        writer.delete_channel(ChannelId(2))?;
+        assert!(writer.get_known_channels(1)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_backfill() -> Result<()> {
+        let db = seeded_db("test_backfill")?;
+        let writer = db.writeable()?;
+        writer.update_channel(3, 1, "memes", true)?;
+        writer.update_channel(4, 1, "hidden", false)?;
+
+        writer.start_server_backfill(1)?;
+        assert_eq!(writer.get_backfills(1)?.len(), 2);
+
+        writer.prioritise_backfill(3)?;
+        let next = writer.get_next_backfill(1)?.unwrap();
+        assert_eq!((next.channel, next.cursor), (3, None));
+
+        writer.advance_backfill(3, Some(100), 50)?;
+        writer.pause_backfill(3)?;
+        assert_eq!(writer.get_next_backfill(1)?.unwrap().channel, 2);
+
+        // resuming keeps the cursor
+        writer.start_backfill(3)?;
+        writer.advance_backfill(3, None, 10)?;
+        let memes = writer
+            .get_backfills(1)?
+            .into_iter()
+            .find(|b| b.channel == 3)
+            .unwrap();
+        assert_eq!((memes.cursor, memes.messages), (Some(100), 60));
+        assert!(memes.done && !memes.paused);
+
+        writer.pause_server_backfill(1)?;
+        assert!(writer.get_next_backfill(1)?.is_none());
+        Ok(())
+    }
+}